@@ -0,0 +1,47 @@
+//! Ciphers with key stretching off, and each KDF at its interactive preset
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cryimg_core::crypto::{
+    decrypt_message, encrypt_message_with_kdf,
+    encrypt::aes_encrypt,
+    kdf::{Kdf, KdfParams, KdfPreset},
+    Algorithm,
+};
+
+const SIZES: [usize; 3] = [1 << 10, 64 << 10, 1 << 20];
+
+fn ciphers(c: &mut Criterion) {
+    let no_kdf = KdfParams::fastest();
+    let mut group = c.benchmark_group("cipher");
+    for len in SIZES {
+        let message = vec![b'a'; len];
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("aes_encrypt", len), &message, |b, message| {
+            b.iter(|| aes_encrypt(message, "bench key", &no_kdf).unwrap())
+        });
+        for algorithm in [Algorithm::Xor, Algorithm::AesGcm, Algorithm::ChaCha20Poly1305] {
+            let name = format!("{algorithm:?}");
+            group.bench_with_input(BenchmarkId::new(format!("{name}/encrypt"), len), &message, |b, message| {
+                b.iter(|| encrypt_message_with_kdf(message, "bench key", algorithm, &no_kdf).unwrap())
+            });
+            let cipher = encrypt_message_with_kdf(&message, "bench key", algorithm, &no_kdf).unwrap();
+            group.bench_with_input(BenchmarkId::new(format!("{name}/decrypt"), len), &cipher, |b, cipher| {
+                b.iter(|| decrypt_message(cipher, "bench key", algorithm).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn kdfs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("kdf");
+    group.sample_size(10);
+    for kdf in [Kdf::Argon2id, Kdf::Scrypt, Kdf::Pbkdf2] {
+        let params = KdfParams::new(kdf, KdfPreset::Interactive);
+        group.bench_function(format!("{kdf:?}"), |b| b.iter(|| params.derive("bench key", b"0123456789abcdef").unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ciphers, kdfs);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+//! Pixel order for PRNG embedding: the Feistel permutation computes each position
+//! on demand, the legacy shuffle has to build and shuffle every index first.
+//! Embedding a short message only visits a handful of positions, so that up-front
+//! cost is what dominates on large images.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cryimg_core::{stegano::permutation::Permutation, utils::Seed};
+
+/// Positions a ~100-byte message needs at one bit per RGB channel, header included
+const STEPS: usize = (21 + 100) * 8 / 3;
+
+fn first_positions(c: &mut Criterion) {
+    let seed = Seed::new("bench");
+    let mut group = c.benchmark_group("first_positions");
+    group.throughput(Throughput::Elements(STEPS as u64));
+    group.sample_size(10);
+
+    for megapixels in [1usize, 16, 64] {
+        let len = megapixels << 20;
+        group.bench_with_input(BenchmarkId::new("shuffle", megapixels), &len, |b, &len| {
+            b.iter(|| {
+                let permutation = Permutation::shuffle(len, &seed);
+                (0..STEPS).map(|i| permutation.get(i)).sum::<usize>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("feistel", megapixels), &len, |b, &len| {
+            b.iter(|| {
+                let permutation = Permutation::feistel(len, &seed);
+                (0..STEPS).map(|i| permutation.get(i)).sum::<usize>()
+            })
+        });
+    }
+
+    // A full shuffle of a gigapixel image needs 8 GiB for the index list alone
+    let gigapixel = 1usize << 30;
+    group.bench_function(BenchmarkId::new("feistel", 1024), |b| {
+        b.iter(|| {
+            let permutation = Permutation::feistel(black_box(gigapixel), &seed);
+            (0..STEPS).map(|i| permutation.get(i)).sum::<usize>()
+        })
+    });
+    group.finish();
+}
+
+/// Cost per position once the order exists, e.g. when a payload fills the image
+fn per_position(c: &mut Criterion) {
+    let seed = Seed::new("bench");
+    let len = 1 << 20;
+    let shuffle = Permutation::shuffle(len, &seed);
+    let feistel = Permutation::feistel(len, &seed);
+
+    let mut group = c.benchmark_group("per_position");
+    group.throughput(Throughput::Elements(len as u64));
+    group.sample_size(10);
+    group.bench_function("shuffle", |b| b.iter(|| (0..len).map(|i| shuffle.get(i)).sum::<usize>()));
+    group.bench_function("feistel", |b| b.iter(|| (0..len).map(|i| feistel.get(i)).sum::<usize>()));
+    group.finish();
+}
+
+criterion_group!(benches, first_positions, per_position);
+criterion_main!(benches);
@@ -0,0 +1,112 @@
+//! Embedding and extraction across cover sizes, payload sizes, pixel order and
+//! cipher. Key stretching is turned off so the numbers measure the stegano path;
+//! `crypto` covers the KDFs on their own.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cryimg_core::{
+    crypto::{Algorithm, kdf::KdfParams},
+    stegano::{
+        embed::{embed_to_image, EmbedOptions},
+        extract::extract_message,
+        message::Message,
+        test_util::cover,
+    },
+    utils::Seed,
+};
+
+const ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::None,
+    Algorithm::Xor,
+    Algorithm::Caesar,
+    Algorithm::Rot13,
+    Algorithm::Aes,
+    Algorithm::AesGcm,
+    Algorithm::ChaCha20Poly1305,
+];
+
+fn payload(len: usize) -> Message {
+    Message::new((0..len).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>())
+}
+
+fn options(algorithm: Algorithm, prng: bool) -> EmbedOptions {
+    EmbedOptions {
+        algorithm,
+        kdf: KdfParams::fastest(),
+        use_prng: prng,
+        seed: prng.then(|| Seed::new("bench")),
+        ..Default::default()
+    }
+}
+
+/// Caesar wants a number, everything else any non-empty key
+fn key(algorithm: Algorithm) -> &'static str {
+    if algorithm == Algorithm::Caesar { "7" } else { "bench key" }
+}
+
+/// Cover side lengths and payload sizes; every payload fits every cover at one bit per channel
+const SIDES: [u32; 3] = [512, 1024, 2048];
+const PAYLOADS: [usize; 3] = [1 << 10, 16 << 10, 64 << 10];
+
+fn embed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("embed");
+    group.sample_size(10);
+    for side in SIDES {
+        let img = cover(side, side);
+        for len in PAYLOADS {
+            let message = payload(len);
+            group.throughput(Throughput::Bytes(len as u64));
+            for prng in [false, true] {
+                let id = BenchmarkId::new(if prng { "prng" } else { "sequential" }, format!("{side}px/{len}B"));
+                let options = options(Algorithm::None, prng);
+                group.bench_with_input(id, &message, |b, message| {
+                    b.iter(|| embed_to_image(&img, message, "", &options).unwrap())
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn extract(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(10);
+    for side in SIDES {
+        let img = cover(side, side);
+        for len in PAYLOADS {
+            group.throughput(Throughput::Bytes(len as u64));
+            for prng in [false, true] {
+                let options = options(Algorithm::None, prng);
+                let stego = embed_to_image(&img, &payload(len), "", &options).unwrap();
+                let id = BenchmarkId::new(if prng { "prng" } else { "sequential" }, format!("{side}px/{len}B"));
+                group.bench_with_input(id, &stego, |b, stego| {
+                    b.iter(|| extract_message(stego, "", prng, options.seed).unwrap())
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+/// Round trip with each cipher on a fixed cover and payload
+fn algorithms(c: &mut Criterion) {
+    let img = cover(1024, 1024);
+    let message = payload(16 << 10);
+    let mut group = c.benchmark_group("algorithm");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(message.data.len() as u64));
+    for algorithm in ALGORITHMS {
+        let options = options(algorithm, true);
+        let name = format!("{algorithm:?}");
+        group.bench_function(BenchmarkId::new("embed", &name), |b| {
+            b.iter(|| embed_to_image(&img, &message, key(algorithm), &options).unwrap())
+        });
+        let stego = embed_to_image(&img, &message, key(algorithm), &options).unwrap();
+        group.bench_function(BenchmarkId::new("extract", &name), |b| {
+            b.iter(|| extract_message(&stego, key(algorithm), true, options.seed).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, embed, extract, algorithms);
+criterion_main!(benches);
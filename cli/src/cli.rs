@@ -1,5 +1,12 @@
-use clap::{Parser, ArgGroup};
-use cryimg_core::crypto::Algorithm;
+use std::str::FromStr;
+
+use clap::{Parser, Subcommand, ArgGroup};
+use cryimg_core::{
+    Error,
+    crypto::{Algorithm, kdf::{Kdf, KdfPreset}},
+    stegano::{channels::Channels, compression::Compression, ecc::Ecc, embed::EmbedStrategy, header::MAX_DEPTH},
+    utils::Seed,
+};
 
 
 /// 🔐 Embed secret messages in images using LSB steganography.
@@ -7,22 +14,42 @@ use cryimg_core::crypto::Algorithm;
 #[clap(
     name = "cimg",
     version,
-    author = "Haridas Mahato <haridasmahato12@gmail.com>", 
-    about = "🔐 Cryimg: Hide messages in images using LSB steganography", 
+    author = "Haridas Mahato <haridasmahato12@gmail.com>",
+    about = "🔐 Cryimg: Embed secret messages in images using LSB steganography",
     long_about = Some(concat!(
-        "🔐 Cryimg: Hide messages in images using LSB steganography\n\
+        "🔐 Cryimg: Embed secret messages in images using LSB steganography\n\
          Author: Haridas Mahato <haridasmahato12@gmail.com>\n\
          Version: ", env!("CARGO_PKG_VERSION"),)
     ),
 )]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// ✉️ Encrypt a message or file and hide it in an image
+    Embed(EmbedArgs),
+    /// 🔍 Recover and decrypt a hidden message or file
+    Extract(ExtractArgs),
+    /// 📊 Check whether a message fits in an image, without writing anything
+    Analyze(AnalyzeArgs),
+    /// 🧾 Print the container header of a stego image (no key needed)
+    Inspect(InspectArgs),
+    /// 🕵️ Estimate whether an image carries hidden data
+    Detect(DetectArgs),
+}
+
+/// Arguments for `cimg embed`
+#[derive(clap::Args, Debug)]
 #[command(group(
-    ArgGroup::new("mode")
+    ArgGroup::new("payload")
         .required(true)
-        .args(["encrypt", "decrypt"])
-        .multiple(false) // Only one allowed
+        .args(["msg", "file"])
 ))]
-pub struct Args {
-    /// 📷 Path to the input image
+pub struct EmbedArgs {
+    /// 📷 Path to the cover image
     #[arg(short, long, help = "Path to the input image file")]
     pub img: String,
 
@@ -30,60 +57,259 @@ pub struct Args {
     #[arg(short, long, help = "Secret message to embed in the image")]
     pub msg: Option<String>,
 
+    /// 📎 File to embed instead of a text message
+    #[arg(short, long, help = "Path to a file to embed (any binary content)")]
+    pub file: Option<String>,
+
     /// 📁 Path to the output image
-    #[arg(short, long, default_value = "output.png", help = "Path to the output image file")]
+    #[arg(short, long, default_value = "output.png", help = "Path to the output image file; a .jpg/.jpeg output hides the message in the DCT coefficients of a JPEG cover")]
     pub out: String,
 
-    /// 🔒 Encryption algorithm: none | xor | caesar | rot13 | aes
-    #[arg(short, long, help = "Encryption algorithm")]
-    pub encrypt: Option<Algorithm>,
-    
-    /// 🔒 Decryption algorithm: none | xor | caesar | rot13 | aes
-    #[arg(short, long, help = "Decryption algorithm")]
-    pub decrypt: Option<Algorithm>,
+    /// 🔒 Encryption algorithm: none | xor | caesar | rot13 | aes | aes-gcm | chacha20-poly1305
+    #[arg(short, long, value_parser = parse_algorithm, help = "Encryption algorithm")]
+    pub encrypt: Algorithm,
 
     /// 🔑 Key for encryption
-    #[arg(long, help = "Key for encryption/decryption (if applicable)")]
+    #[arg(long, help = "Key for encryption (if applicable)")]
+    pub key: Option<String>,
+
+    /// 🧂 Key derivation function for aes | aes-gcm | chacha20-poly1305
+    #[arg(long, value_enum, default_value_t = Kdf::Argon2id, help = "Key derivation function used to stretch the key")]
+    pub kdf: Kdf,
+
+    /// ⏱️ Cost preset for the key derivation function
+    #[arg(long, value_enum, default_value_t = KdfPreset::Moderate, help = "KDF cost preset: slower presets resist brute force better")]
+    pub kdf_cost: KdfPreset,
+
+    #[command(flatten)]
+    pub order: OrderArgs,
+
+    /// 🎭 How bits are written into the pixels
+    #[arg(long, value_enum, default_value_t = EmbedStrategy::Replace, help = "Embedding strategy: match (±1) resists chi-square detection")]
+    pub strategy: EmbedStrategy,
+
+    /// 🧮 Low bits per channel used for the payload
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=MAX_DEPTH as i64), help = "Bits per channel (1-4): more bits hold more data but distort the image more")]
+    pub depth: u8,
+
+    /// 🎨 Which channels of each pixel carry the payload
+    #[arg(long, value_enum, default_value_t = Channels::Auto, help = "Channels to embed into: auto leaves an opaque alpha channel untouched")]
+    pub channels: Channels,
+
+    /// 🗜️ Compression applied before encryption
+    #[arg(long, value_enum, default_value_t = Compression::Auto, help = "Compress the message before encrypting it: auto uses whichever codec saves the most")]
+    pub compress: Compression,
+
+    /// 🩹 Reed-Solomon error correction added after encryption
+    #[arg(long, value_enum, default_value_t = Ecc::None, help = "Error correction, so the message survives a few damaged pixels: low, medium, high or max parity")]
+    pub ecc: Ecc,
+}
+
+/// Arguments for `cimg extract`
+#[derive(clap::Args, Debug)]
+pub struct ExtractArgs {
+    /// 📷 Path to the stego image
+    #[arg(short, long, help = "Path to the input image file")]
+    pub img: String,
+
+    /// 🔑 Key for decryption
+    #[arg(long, help = "Key for decryption (if applicable)")]
     pub key: Option<String>,
 
+    /// 💾 Where to write the extracted bytes
+    #[arg(long, help = "Write the extracted payload to this file instead of printing it")]
+    pub out_file: Option<String>,
+
+    #[command(flatten)]
+    pub order: OrderArgs,
+
+    /// 🗝️ Read images produced before the container header existed
+    #[arg(long, help = "Extract using the legacy length-prefix format (images made by older versions)")]
+    pub legacy: bool,
+
+    /// 🌱 Derive the PRNG order the way older versions did
+    #[arg(long, requires = "prng", help = "Hash --seed with the legacy derivation (images embedded with --prng by older versions)")]
+    pub legacy_seed: bool,
+
+    /// 🔀 Fall back to the full shuffle older versions used for the PRNG order
+    #[arg(long, help = "Also try the fully shuffled PRNG order of images embedded before the Feistel order (uses 8 bytes of memory per pixel)")]
+    pub legacy_order: bool,
+
+    /// 🔒 Decryption algorithm: none | xor | caesar | rot13 | aes | aes-gcm | chacha20-poly1305
+    #[arg(short, long, value_parser = parse_algorithm, help = "Decryption algorithm; read from the image header and only needed with --legacy")]
+    pub decrypt: Option<Algorithm>,
+
+    /// 🔤 Output the ciphertext as base64 text instead of decrypting it
+    #[arg(long, conflicts_with = "legacy", help = "Output the still-encrypted payload as base64 text (for copy and paste) instead of decrypting it")]
+    pub armor: bool,
+}
+
+/// Arguments for `cimg analyze`
+#[derive(clap::Args, Debug)]
+#[command(group(
+    ArgGroup::new("payload")
+        .required(true)
+        .args(["msg", "file"])
+))]
+pub struct AnalyzeArgs {
+    /// 📷 Path to the cover image
+    #[arg(short, long, help = "Path to the input image file")]
+    pub img: String,
+
+    /// ✉️ Message to measure
+    #[arg(short, long, help = "Secret message that would be embedded")]
+    pub msg: Option<String>,
+
+    /// 📎 File to measure instead of a text message
+    #[arg(short, long, help = "Path to a file that would be embedded")]
+    pub file: Option<String>,
+
+    /// 🔒 Encryption algorithm: none | xor | caesar | rot13 | aes | aes-gcm | chacha20-poly1305
+    #[arg(short, long, value_parser = parse_algorithm, help = "Encryption algorithm")]
+    pub encrypt: Algorithm,
+
+    /// 🎨 Which channels of each pixel would carry the payload
+    #[arg(long, value_enum, default_value_t = Channels::Auto, help = "Channels to embed into: auto leaves an opaque alpha channel untouched")]
+    pub channels: Channels,
+
+    /// 🗜️ Compression applied before encryption
+    #[arg(long, value_enum, default_value_t = Compression::Auto, help = "Compress the message before encrypting it: auto uses whichever codec saves the most")]
+    pub compress: Compression,
+
+    /// 🩹 Reed-Solomon error correction added after encryption
+    #[arg(long, value_enum, default_value_t = Ecc::None, help = "Error correction, so the message survives a few damaged pixels: low, medium, high or max parity")]
+    pub ecc: Ecc,
+}
+
+/// Arguments for `cimg inspect`
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    /// 📷 Path to the stego image
+    #[arg(short, long, help = "Path to the input image file")]
+    pub img: String,
+
+    /// 🔑 Key the order was derived from
+    #[arg(long, help = "Key (only needed with --key-order)")]
+    pub key: Option<String>,
+
+    #[command(flatten)]
+    pub order: OrderArgs,
+
+    /// 🌱 Derive the PRNG order the way older versions did
+    #[arg(long, requires = "prng", help = "Hash --seed with the legacy derivation (images embedded with --prng by older versions)")]
+    pub legacy_seed: bool,
+
+    /// 🔀 Fall back to the full shuffle older versions used for the PRNG order
+    #[arg(long, help = "Also try the fully shuffled PRNG order of images embedded before the Feistel order (uses 8 bytes of memory per pixel)")]
+    pub legacy_order: bool,
+}
+
+/// Arguments for `cimg detect`
+#[derive(clap::Args, Debug)]
+pub struct DetectArgs {
+    /// 📷 Path to the suspect image
+    #[arg(short, long, help = "Path to the input image file")]
+    pub img: String,
+
+    /// 🔑 Key the order was derived from
+    #[arg(long, help = "Key (only needed with --key-order)")]
+    pub key: Option<String>,
+
+    #[command(flatten)]
+    pub order: OrderArgs,
+
+    /// 🌱 Derive the PRNG order the way older versions did
+    #[arg(long, requires = "prng", help = "Hash --seed with the legacy derivation (images embedded with --prng by older versions)")]
+    pub legacy_seed: bool,
+
+    /// 🔀 Fall back to the full shuffle older versions used for the PRNG order
+    #[arg(long, help = "Also try the fully shuffled PRNG order of images embedded before the Feistel order (uses 8 bytes of memory per pixel)")]
+    pub legacy_order: bool,
+}
+
+/// 🎲 Pixel ordering shared by the subcommands that read or write a payload
+#[derive(clap::Args, Debug)]
+pub struct OrderArgs {
     /// 🎲 Use pseudorandom embedding order
     #[arg(long, help = "Enable PRNG-based pixel scrambling")]
     pub prng: bool,
 
     /// 🌱 Seed for the PRNG
-    #[arg(long, default_value = "", help = "Seed for PRNG (only if --prng is enabled) | Can be any string (internally hashed to a u64)")]
+    #[arg(long, default_value = "", help = "Seed for PRNG (only if --prng is enabled) | Can be any string (hashed with SHA-256 into a 256-bit seed)")]
     pub seed: String,
 
-    /// 🕵️ Analyze with the image and the msg
-    #[arg(short, long, help = "Analyze with the image and the msg")]
-    pub analyze: bool,
+    /// 🗝️ Derive the pseudorandom order from the key
+    #[arg(long, conflicts_with_all = ["prng", "seed"], help = "Scramble the pixel order with a permutation derived from --key, so no separate seed is needed")]
+    pub key_order: bool,
 }
 
-impl Args {
-    pub fn algorithm(&self) -> Result<Algorithm, String> {
-        match (&self.encrypt, &self.decrypt) {
-            (Some(e), None) => Ok(*e),
-            (None, Some(d)) => Ok(*d),
-            (Some(_), Some(_)) => Err("Cannot specify both --encrypt and --decrypt.".into()),
-            (None, None) => Err("Either --encrypt or --decrypt must be specified.".into()),
+impl OrderArgs {
+    pub fn uses_prng(&self) -> bool {
+        self.prng || self.key_order
+    }
+
+    /// The seed for `--seed` (derived with the legacy hasher if `legacy` is set),
+    /// or the one derived from `key` with `--key-order`
+    pub fn seed(&self, key: &str, legacy: bool) -> Result<Option<Seed>, Error> {
+        if self.key_order {
+            Seed::from_key(key).map(Some)
+        } else if self.seed.is_empty() {
+            Ok(None)
+        } else if legacy {
+            Ok(Some(Seed::legacy(&self.seed)))
+        } else {
+            Ok(Some(Seed::new(&self.seed)))
         }
     }
 
-    pub fn validate(&self) -> Result<(), String> {
+    fn validate(&self, key: &Option<String>) -> Result<(), String> {
         // Ensure PRNG needs seed
         if self.prng && self.seed.is_empty() {
             return Err("PRNG is enabled, but seed is missing. Provide a seed using --seed.".into());
         }
+        if self.key_order && key.as_deref().is_none_or(str::is_empty) {
+            return Err("--key-order derives the order from the key; provide one using --key.".into());
+        }
+        Ok(())
+    }
+}
 
-        // Validate algorithm and key requirement
-        let algo = self.algorithm()?;
-
-        if matches!(algo, Algorithm::Xor | Algorithm::Caesar | Algorithm::Aes) && self.key.is_none() {
-            return Err(format!("Encryption algorithm '{:?}' requires a non-empty key.", algo));
+impl Args {
+    /// Reject flag combinations clap cannot express, before any work is done
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.command {
+            Command::Embed(args) => {
+                args.order.validate(&args.key)?;
+                require_key(args.encrypt, &args.key)
+            }
+            Command::Extract(args) => {
+                args.order.validate(&args.key)?;
+                // The header names the algorithm; only legacy extraction needs it spelled out
+                match (args.legacy, args.decrypt) {
+                    (true, None) => Err("--legacy requires the decryption algorithm, e.g. --decrypt aes.".into()),
+                    (true, Some(algo)) => require_key(algo, &args.key),
+                    (false, _) => Ok(()),
+                }
+            }
+            // Sizes do not depend on the key
+            Command::Analyze(_) => Ok(()),
+            Command::Inspect(args) => args.order.validate(&args.key),
+            Command::Detect(args) => args.order.validate(&args.key),
         }
+    }
+}
 
-        Ok(())
+fn require_key(algo: Algorithm, key: &Option<String>) -> Result<(), String> {
+    if algo.requires_key() && key.as_deref().is_none_or(str::is_empty) {
+        return Err(format!("Encryption algorithm '{:?}' requires a non-empty key.", algo));
     }
+    Ok(())
+}
+
+/// Accepts the same names as [`Algorithm::from_str`], so typos are reported as
+/// "Unsupported algorithm" rather than a generic clap error
+fn parse_algorithm(s: &str) -> Result<Algorithm, cryimg_core::Error> {
+    Algorithm::from_str(s)
 }
 
 
@@ -102,65 +328,187 @@ mod tests {
                 .collect();
             Args::try_parse_from(argv)
         }
+
+    fn parse_embed(args: &[&str]) -> EmbedArgs {
+        match parse_args(std::iter::once("embed").chain(args.iter().copied())).unwrap().command {
+            Command::Embed(args) => args,
+            other => panic!("expected embed, got {other:?}"),
+        }
+    }
+
+    fn parse_extract(args: &[&str]) -> ExtractArgs {
+        match parse_args(std::iter::once("extract").chain(args.iter().copied())).unwrap().command {
+            Command::Extract(args) => args,
+            other => panic!("expected extract, got {other:?}"),
+        }
+    }
+
     #[test]
     fn cli_basic_args() {
-        let args = parse_args(&[
-            "--img", "test.png", 
+        let args = parse_embed(&[
+            "--img", "test.png",
             "--msg", "Hello, World!",
             "--encrypt", "none",
-        ]).unwrap();
+        ]);
         assert_eq!(args.img, "test.png");
         assert_eq!(args.msg.clone().unwrap(), "Hello, World!");
         assert_eq!(args.out, "output.png");
-        assert_eq!(args.algorithm().unwrap(), Algorithm::None);
-        assert_eq!(args.decrypt, None);
+        assert_eq!(args.encrypt, Algorithm::None);
         assert_eq!(args.key.unwrap_or_default(), "");
-        assert!(!args.prng);
-        assert_eq!(args.seed, "");
+        assert!(!args.order.prng);
+        assert_eq!(args.order.seed, "");
+        assert_eq!(args.kdf, Kdf::Argon2id);
+        assert_eq!(args.kdf_cost, KdfPreset::Moderate);
+        assert_eq!(args.strategy, EmbedStrategy::Replace);
+    }
+
+    #[test]
+    fn cli_strategy_choice() {
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Matched",
+            "--encrypt", "none",
+            "--strategy", "match",
+        ]);
+        assert_eq!(args.strategy, EmbedStrategy::Match);
+        assert_eq!(args.depth, 1);
+        assert_eq!(args.channels, Channels::Auto);
+    }
+
+    #[test]
+    fn cli_channels_choice() {
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Blue only",
+            "--encrypt", "none",
+            "--channels", "blue",
+        ]);
+        assert_eq!(args.channels, Channels::Blue);
+        assert_eq!(args.compress, Compression::Auto);
+
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Stored as is",
+            "--encrypt", "none",
+            "--compress", "none",
+        ]);
+        assert_eq!(args.compress, Compression::None);
+        assert_eq!(args.ecc, Ecc::None);
+
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Sturdy",
+            "--encrypt", "none",
+            "--ecc", "high",
+        ]);
+        assert_eq!(args.ecc, Ecc::High);
+    }
+
+    #[test]
+    fn cli_depth_range() {
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Deeper",
+            "--encrypt", "none",
+            "--depth", "4",
+        ]);
+        assert_eq!(args.depth, 4);
+
+        for depth in ["0", "5"] {
+            let result = parse_args(&[
+                "embed",
+                "--img", "test.png",
+                "--msg", "Too deep",
+                "--encrypt", "none",
+                "--depth", depth,
+            ]);
+            assert!(result.is_err(), "{depth}");
+        }
+    }
+
+    #[test]
+    fn cli_file_and_out_file() {
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--file", "archive.zip",
+            "--encrypt", "aes-gcm",
+            "--key", "k",
+        ]);
+        assert_eq!(args.file.unwrap(), "archive.zip");
+
+        let args = parse_extract(&[
+            "--img", "stego.png",
+            "--out-file", "restored.zip",
+        ]);
+        assert_eq!(args.out_file.unwrap(), "restored.zip");
+
+        let conflict = parse_args(&[
+            "embed",
+            "--img", "test.png",
+            "--msg", "text",
+            "--file", "archive.zip",
+            "--encrypt", "none",
+        ]);
+        assert!(conflict.is_err());
+    }
+
+    #[test]
+    fn cli_kdf_choice() {
+        let args = parse_embed(&[
+            "--img", "test.png",
+            "--msg", "Stretched",
+            "--encrypt", "aes-gcm",
+            "--key", "passphrase",
+            "--kdf", "scrypt",
+            "--kdf-cost", "sensitive",
+        ]);
+        assert_eq!(args.kdf, Kdf::Scrypt);
+        assert_eq!(args.kdf_cost, KdfPreset::Sensitive);
     }
 
     #[test]
     fn cli_encrypt_and_key() {
-        let args = parse_args(&[
+        let args = parse_embed(&[
             "--img", "test.png",
             "--msg", "Secret Message",
             "--out", "output.png",
             "--encrypt", "xor",
             "--key", "mysecretkey",
-        ]).unwrap();
-        assert_eq!(args.algorithm().unwrap(), Algorithm::Xor);
+        ]);
+        assert_eq!(args.encrypt, Algorithm::Xor);
         assert_eq!(args.key.unwrap(), "mysecretkey");
     }
 
     #[test]
     fn cli_prng_and_seed() {
-        let args = parse_args(&[
+        let args = parse_embed(&[
             "--img", "test.png",
             "--msg", "Randomized Message",
             "--out", "output.png",
             "--prng",
             "--seed", "12345",
             "--encrypt", "none",
-        ]).unwrap();
-        assert!(args.prng);
-        assert_eq!(args.seed, "12345");
+        ]);
+        assert!(args.order.prng);
+        assert_eq!(args.order.seed, "12345");
+        assert_eq!(args.order.seed("", false).unwrap(), Some(Seed::new("12345")));
+        assert_eq!(args.order.seed("", true).unwrap(), Some(Seed::legacy("12345")));
     }
 
     #[test]
     fn cli_decrypt_and_key() {
-        let args = parse_args(&[
+        let args = parse_extract(&[
             "--img", "test.png",
-            "--msg", "Default Test",
             "--decrypt", "xor",
             "--key", "mysecretkey",
-        ]).unwrap();
-        assert_eq!(args.algorithm().unwrap(), Algorithm::Xor);
+        ]);
+        assert_eq!(args.decrypt, Some(Algorithm::Xor));
         assert_eq!(args.key.unwrap(), "mysecretkey");
     }
 
     #[test]
     fn cli_all_args() {
-        let args = parse_args(&[
+        let args = parse_embed(&[
             "--img", "test.png",
             "--msg", "Full Test Message",
             "--out", "output.png",
@@ -168,14 +516,14 @@ mod tests {
             "--key", "supersecretkey",
             "--prng",
             "--seed", "54321",
-        ]).unwrap();
+        ]);
         assert_eq!(args.img, "test.png");
         assert_eq!(args.msg.clone().unwrap(), "Full Test Message");
         assert_eq!(args.out, "output.png");
-        assert_eq!(args.algorithm().unwrap(), Algorithm::Aes);
+        assert_eq!(args.encrypt, Algorithm::Aes);
         assert_eq!(args.key.unwrap(), "supersecretkey");
-        assert!(args.prng);
-        assert_eq!(args.seed, "54321");
+        assert!(args.order.prng);
+        assert_eq!(args.order.seed, "54321");
     }
 
     #[test]
@@ -185,13 +533,43 @@ mod tests {
         let help = cmd.render_help().to_string();
         println!("{}", help);
         assert!(help.contains("Embed secret messages"));
+        for subcommand in ["embed", "extract", "analyze", "inspect", "detect"] {
+            assert!(help.contains(subcommand), "{subcommand}");
+        }
+
+        let help = cmd.find_subcommand_mut("embed").unwrap().render_help().to_string();
         assert!(help.contains("-i, --img"));
         assert!(help.contains("--encrypt"));
     }
 
+    #[test]
+    fn cli_key_order() {
+        let args = parse_args(&[
+            "embed",
+            "--img", "test.png",
+            "--msg", "One secret",
+            "--encrypt", "aes-gcm",
+            "--key", "passphrase",
+            "--key-order",
+        ]).unwrap();
+        assert!(args.validate().is_ok());
+        let Command::Embed(embed) = args.command else { unreachable!() };
+        assert!(embed.order.uses_prng());
+
+        let keyless = parse_args(&["extract", "--img", "stego.png", "--key-order"]).unwrap();
+        assert_eq!(
+            keyless.validate().unwrap_err(),
+            "--key-order derives the order from the key; provide one using --key."
+        );
+
+        let with_seed = parse_args(&["extract", "--img", "stego.png", "--key-order", "--seed", "s"]);
+        assert!(with_seed.is_err());
+    }
+
     #[test]
     fn cli_missing_key_for_xor() {
         let args = parse_args(&[
+            "embed",
             "--img", "test.png",
             "--msg", "Oops",
             "--encrypt", "xor",
@@ -204,6 +582,7 @@ mod tests {
     #[test]
     fn cli_prng_without_seed() {
         let args = parse_args(&[
+            "embed",
             "--img", "test.png",
             "--msg", "Oops",
             "--prng",
@@ -217,6 +596,7 @@ mod tests {
     #[test]
     fn cli_invalid_algorithm() {
         let args = parse_args(&[
+            "embed",
             "--img", "test.png",
             "--msg", "Invalid Algorithm",
             "--encrypt", "invalid_algo",
@@ -227,26 +607,76 @@ mod tests {
     }
 
     #[test]
-    fn cli_both_encrypt_and_decrypt() {
-        let result = Args::try_parse_from([
+    fn cli_extract_rejects_embed_flags() {
+        let result = parse_args(&[
+            "extract",
             "--img", "test.png",
-            "--msg", "Conflicting Options",
-            "--encrypt", "xor",
-            "--decrypt", "aes",
+            "--msg", "Not here",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cli_embed_requires_payload() {
+        let result = parse_args(&[
+            "embed",
+            "--img", "test.png",
+            "--encrypt", "none",
         ]);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("<--encrypt <ENCRYPT>|--decrypt <DECRYPT>>"));
     }
 
     #[test]
-    fn cli_analyze_flag() {
+    fn cli_legacy_requires_algorithm() {
+        let args = parse_args(&["extract", "--img", "old.png", "--legacy"]).unwrap();
+        assert_eq!(
+            args.validate().unwrap_err(),
+            "--legacy requires the decryption algorithm, e.g. --decrypt aes."
+        );
+    }
+
+    #[test]
+    fn cli_decrypt_without_algorithm() {
         let args = parse_args(&[
+            "extract",
+            "--img", "stego.png",
+            "--key", "mysecretkey",
+        ]).unwrap();
+        assert!(args.validate().is_ok());
+        let Command::Extract(args) = args.command else { unreachable!() };
+        assert_eq!(args.decrypt, None);
+    }
+
+    #[test]
+    fn cli_armor() {
+        let args = parse_extract(&["--img", "stego.png", "--armor"]);
+        assert!(args.armor);
+        assert!(!parse_extract(&["--img", "stego.png"]).armor);
+        assert!(parse_args(&["extract", "--img", "old.png", "--legacy", "--armor"]).is_err());
+    }
+
+    #[test]
+    fn cli_inspect_and_detect() {
+        let args = parse_args(&["inspect", "--img", "stego.png", "--prng", "--seed", "s"]).unwrap();
+        assert!(matches!(args.command, Command::Inspect(InspectArgs { ref order, .. }) if order.prng));
+
+        let args = parse_args(&["detect", "--img", "suspect.png"]).unwrap();
+        assert!(matches!(args.command, Command::Detect(DetectArgs { ref order, .. }) if !order.uses_prng()));
+
+        let args = parse_args(&["detect", "--img", "suspect.png", "--key-order", "--key", "k", "--legacy-order"]).unwrap();
+        assert!(matches!(args.command, Command::Detect(DetectArgs { ref order, legacy_order: true, .. }) if order.key_order));
+        assert!(parse_args(&["detect", "--img", "suspect.png", "--prng"]).unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn cli_analyze_subcommand() {
+        let args = parse_args(&[
+            "analyze",
             "--img", "test.png",
             "--msg", "Analyze Test",
             "--encrypt", "none",
-            "--analyze",
         ]).unwrap();
-        assert!(args.analyze);
+        assert!(matches!(args.command, Command::Analyze(_)));
     }
-    
-}
\ No newline at end of file
+
+}
@@ -0,0 +1,56 @@
+use cryimg_core::Error;
+
+/// Generic failure: bad usage or anything without a dedicated code
+pub const FAILURE: i32 = 1;
+
+/// Process exit code for each kind of library error, so scripts can
+/// tell a wrong key from a full image without parsing stderr
+pub fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::Io(_) => 3,
+        Error::CapacityExceeded { .. } => 4,
+        Error::MissingSeed => 5,
+        Error::BadKey(_) => 6,
+        Error::Decryption(_) => 7,
+        Error::Authentication => 8,
+        Error::NoPayload => 9,
+        Error::CorruptHeader(_) => 10,
+        Error::CorruptPayload(_) => 11,
+        Error::UnsupportedFormat(_) => 12,
+        Error::InvalidArgument(_) => 13,
+    }
+}
+
+/// Report `err` on stderr and exit with its code
+pub fn fail(context: &str, err: &Error) -> ! {
+    eprintln!("{context}: {err}");
+    std::process::exit(exit_code(err));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            Error::Io(String::new()),
+            Error::CapacityExceeded { needed: 0, available: 0 },
+            Error::MissingSeed,
+            Error::BadKey(String::new()),
+            Error::Decryption(String::new()),
+            Error::Authentication,
+            Error::NoPayload,
+            Error::CorruptHeader(String::new()),
+            Error::CorruptPayload(String::new()),
+            Error::UnsupportedFormat(String::new()),
+            Error::InvalidArgument(String::new()),
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(exit_code).collect();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert!(!codes.contains(&0) && !codes.contains(&FAILURE) && !codes.contains(&2));
+    }
+}
@@ -1 +1 @@
-pub mod cli;
\ No newline at end of file
+pub mod cli;pub mod exit;
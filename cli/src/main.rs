@@ -1,142 +1,364 @@
 use cryimg_core::{
-    crypto::{encrypt_message, decrypt_message}, 
-    stegano, 
-    utils::string_to_seed
+    Error,
+    crypto::{armor, dearmor, decrypt_message_with_kdf, kdf::{Kdf, KdfParams}},
+    stegano::{
+        self, compression::Compression, ecc::Ecc, embed::EmbedOptions, extract::ExtractOptions, message::Message,
+        output::OutputFormat, verify::Metrics,
+    },
+};
+use cli::{
+    cli::{Args, Command, EmbedArgs, ExtractArgs, AnalyzeArgs, InspectArgs, DetectArgs},
+    exit::{fail, FAILURE},
 };
-use cli::cli::Args;
 use clap::Parser;
 
 fn main() {
     let args = Args::parse();
 
-    let algo = args.algorithm().unwrap();
+    if let Err(e) = args.validate() {
+        eprintln!("Error: {e}");
+        std::process::exit(FAILURE);
+    }
 
-    let seed: Option<u64> = if args.seed.is_empty() {
-        None
-    } else {
-        Some(string_to_seed(&args.seed))
-    };
-
-    if args.encrypt.is_some() {
-        // Encrypt mode
-        let img = image::open(&args.img).unwrap_or_else(|e| {
-            eprintln!("Failed to open image: {e}");
-            std::process::exit(1);
-        });
+    match args.command {
+        Command::Embed(args) => embed(args),
+        Command::Extract(args) => extract(args),
+        Command::Analyze(args) => analyze(args),
+        Command::Inspect(args) => inspect(args),
+        Command::Detect(args) => detect(args),
+    }
+}
+
+fn embed(args: EmbedArgs) {
+    let message = read_message(&args.msg, &args.file);
+    let key = args.key.as_deref().unwrap_or("");
+    // Pixel LSBs would not survive a JPEG encoder, so JPEG output embeds in the DCT domain
+    match OutputFormat::from_path(&args.out) {
+        Ok(OutputFormat::Jpeg) => return embed_jpeg(&args, &message, key),
+        Ok(OutputFormat::Lossless(_)) => {}
+        Err(e) => fail("Error", &e),
+    }
+    let img = open_image(&args.img);
 
-        let analysis = stegano::analyze::analyze_capacity(
-            &img,
-            args.msg.as_deref().unwrap_or(""),
-            algo,
-            args.key.as_deref().unwrap_or(""),
-        ).unwrap_or_else(|e| {
-            eprintln!("Analysis failed: {e}");
-            std::process::exit(1);
+    let analysis = stegano::analyze::analyze_capacity(&img, &message.encode(), args.encrypt, args.channels, args.compress, args.ecc)
+        .unwrap_or_else(|e| fail("Analysis failed", &e));
+
+    let capacity = &analysis.depths[args.depth as usize - 1];
+    if !capacity.can_fit {
+        fail("Error", &Error::CapacityExceeded {
+            needed: analysis.total_payload_bytes,
+            available: capacity.max_capacity_bytes,
         });
+    }
+
+    println!(
+        "✅ Image dimensions: {}x{}, Max capacity: {} bytes, \
+        Message length: {} bytes, Compression: {:?}, Algorithm: {:?}, Bits per channel: {}",
+        analysis.image_dimensions.0,
+        analysis.image_dimensions.1,
+        capacity.max_capacity_bytes,
+        analysis.input_text_len,
+        analysis.compression,
+        args.encrypt,
+        args.depth
+    );
+
+    // Already resolved by the analysis, so `auto` does not try every codec twice
+    let options = embed_options(&args, key, analysis.compression);
+    let verification = stegano::embed::embed_plaintext(&img, &message, key, &options, &args.out)
+        .unwrap_or_else(|e| fail("Embedding failed", &e));
+
+    println!("✅ Message embedded into {}", args.out);
+    report_verification(&args.out, &verification.metrics);
+}
+
+/// Embed into the DCT coefficients of a JPEG cover, for JPEG output: saving
+/// pixels as JPEG would quantize away anything hidden in their low bits
+fn embed_jpeg(args: &EmbedArgs, message: &Message, key: &str) {
+    let cover = read_file(&args.img);
+    if !stegano::jpeg::is_jpeg(&cover) {
+        fail("Error", &Error::UnsupportedFormat(format!(
+            "{} is not a JPEG; JPEG output needs a JPEG cover, use a .png output for other images", args.img
+        )));
+    }
+    let capacity = stegano::jpeg::jpeg_capacity(&cover).unwrap_or_else(|e| fail("Analysis failed", &e));
+
+    println!(
+        "✅ JPEG cover, Estimated capacity: {capacity} bytes, Message length: {} bytes, Algorithm: {:?}",
+        message.encode().len(),
+        args.encrypt
+    );
+
+    let options = embed_options(args, key, args.compress);
+    let verification = stegano::jpeg::embed_jpeg_file(&cover, message, key, &options, &args.out)
+        .unwrap_or_else(|e| fail("Embedding failed", &e));
+
+    println!("✅ Message embedded into the DCT coefficients of {}", args.out);
+    report_verification(&args.out, &verification.metrics);
+}
+
+/// Print how much embedding changed the cover; the written image has already
+/// been read back and checked by the embedding itself
+fn report_verification(out: &str, metrics: &Metrics) {
+    println!("🔬 Verified: {out} reads back the embedded payload");
+    println!(
+        "📐 MSE: {:.4}, PSNR: {:.2} dB, SSIM: {:.4}, Changed pixels: {} of {} ({:.2}%), Changed channel values: {}",
+        metrics.mse,
+        metrics.psnr,
+        metrics.ssim,
+        metrics.changed_pixels,
+        metrics.pixels,
+        100.0 * metrics.changed_pixels as f64 / metrics.pixels.max(1) as f64,
+        metrics.changed_channels
+    );
+}
+
+fn embed_options(args: &EmbedArgs, key: &str, compression: Compression) -> EmbedOptions {
+    EmbedOptions {
+        algorithm: args.encrypt,
+        kdf: KdfParams::new(args.kdf, args.kdf_cost),
+        use_prng: args.order.uses_prng(),
+        seed: args.order.seed(key, false).unwrap_or_else(|e| fail("Error", &e)),
+        strategy: args.strategy,
+        depth: args.depth,
+        channels: args.channels,
+        compression,
+        ecc: args.ecc,
+    }
+}
+
+fn extract(args: ExtractArgs) {
+    let key = args.key.as_deref().unwrap_or("");
+    // Legacy images predate stable seeds as well
+    let seed = args.order.seed(key, args.legacy || args.legacy_seed)
+        .unwrap_or_else(|e| fail("Error", &e));
+
+    if args.legacy {
+        // Legacy images carry no header, so the algorithm must be given (checked by validate)
+        let algo = args.decrypt.expect("validated: --legacy requires --decrypt");
 
-        if args.analyze {
-            println!(
-                "\nStego Analysis Report:\n\
-                \tImage dimensions: {}x{}, \
-                \n\tMax capacity: {} bytes, \
-                \n\tInput text length: {} bytes, \
-                \n\tEncrypted message length: {} bytes, \
-                \n\tPrefix length: {} bytes, \
-                \n\tTotal Payload length: {} bytes, \
-                \n\tAlgorithm: {:?}, \
-                \n\tCan fit: {}",
-
-                analysis.image_dimensions.0,
-                analysis.image_dimensions.1,
-                analysis.max_capacity_bytes,
-                analysis.input_text_len,
-                analysis.encrypted_len.unwrap_or(0),
-                analysis.prefix_overhead_bytes,
-                analysis.total_payload_bytes,
-                algo,
-                analysis.can_fit
-            );
-            std::process::exit(0);
+        let (extracted, warning) =
+            stegano::extract::extract_legacy_message(&open_image(&args.img), args.order.uses_prng(), seed)
+                .unwrap_or_else(|e| fail("Extraction failed", &e));
+        if let Some(warning) = warning {
+            eprintln!("⚠️ Warning: {warning}");
         }
 
-        if !analysis.can_fit {
-            eprintln!(
-                "Error: The message is too long to fit in the image. \
-                Max capacity: {} bytes, Message length: {} bytes",
-                analysis.max_capacity_bytes,
-                analysis.total_payload_bytes
-            );
-            std::process::exit(1);
+        println!("🕵️ Extracted (raw): {extracted}");
+
+        // Older versions base64-encoded binary ciphertext before embedding it
+        let cipher = if algo.legacy_armored() {
+            dearmor(extracted.as_bytes()).unwrap_or_else(|e| fail("Decryption failed", &e))
+        } else {
+            extracted.into_bytes()
+        };
+        let final_msg = decrypt_message_with_kdf(&cipher, key, algo, Kdf::Sha256)
+            .unwrap_or_else(|e| fail("Decryption failed", &e));
+
+        write_output(&Message::new(final_msg), args.out_file.as_deref());
+        return;
+    }
+
+    let extract_options =
+        ExtractOptions { legacy_order: args.legacy_order, ..ExtractOptions::new(args.order.uses_prng(), seed) };
+
+    if args.armor {
+        let (header, payload, _) = match read_jpeg(&args.img) {
+            Some(data) => stegano::jpeg::extract_jpeg_payload(&data, args.order.uses_prng(), seed),
+            None => stegano::extract::extract_payload_with_options(&open_image(&args.img), &extract_options),
         }
+        .unwrap_or_else(|e| fail("Extraction failed", &e));
+        // Version 2 containers already hold base64 text
+        let text = if header.armored() { String::from_utf8_lossy(&payload).into_owned() } else { armor(&payload) };
 
+        println!("🕵️ Detected algorithm: {:?}, compression: {:?}", header.algorithm, header.compression);
+        match args.out_file.as_deref() {
+            Some(path) => write_output(&Message::new(text.into_bytes()), Some(path)),
+            None => println!("🔒 Ciphertext (base64): {text}"),
+        }
+        return;
+    }
+
+    let (header, message, corrected) = match read_jpeg(&args.img) {
+        Some(data) => stegano::jpeg::extract_jpeg(&data, key, args.order.uses_prng(), seed),
+        None => stegano::extract::extract_message_with_options(&open_image(&args.img), key, &extract_options),
+    }
+    .unwrap_or_else(|e| fail("Extraction failed", &e));
+
+    if let Some(expected) = args.decrypt
+        && expected != header.algorithm
+    {
+        eprintln!(
+            "⚠️ Warning: --decrypt {:?} ignored, the image header says it was embedded with {:?}",
+            expected, header.algorithm
+        );
+    }
+
+    println!("🕵️ Detected algorithm: {:?}", header.algorithm);
+    if header.ecc != Ecc::None {
+        println!("🩹 Error correction ({:?}) repaired {corrected} bytes", header.ecc);
+    }
+    write_output(&message, args.out_file.as_deref());
+}
+
+fn analyze(args: AnalyzeArgs) {
+    let img = open_image(&args.img);
+    let message = read_message(&args.msg, &args.file);
+
+    let analysis = stegano::analyze::analyze_capacity(&img, &message.encode(), args.encrypt, args.channels, args.compress, args.ecc)
+        .unwrap_or_else(|e| fail("Analysis failed", &e));
+
+    println!(
+        "\nStego Analysis Report:\n\
+        \tImage dimensions: {}x{}, \
+        \n\tMax capacity: {} bytes, \
+        \n\tInput text length: {} bytes, \
+        \n\tCompression: {:?} ({} bytes), \
+        \n\tEncrypted message length: {} bytes, \
+        \n\tPrefix length: {} bytes, \
+        \n\tError correction: {:?} ({} bytes of parity), \
+        \n\tTotal Payload length: {} bytes, \
+        \n\tAlgorithm: {:?}, \
+        \n\tChannels: {:?}, \
+        \n\tCan fit: {}",
+
+        analysis.image_dimensions.0,
+        analysis.image_dimensions.1,
+        analysis.max_capacity_bytes,
+        analysis.input_text_len,
+        analysis.compression,
+        analysis.compressed_len.unwrap_or(analysis.input_text_len),
+        analysis.encrypted_len.unwrap_or(0),
+        analysis.prefix_overhead_bytes,
+        analysis.ecc,
+        analysis.ecc_overhead_bytes,
+        analysis.total_payload_bytes,
+        args.encrypt,
+        analysis.channels,
+        analysis.can_fit
+    );
+
+    println!("\n\tBits/channel | Capacity (bytes) | Fits | Expected PSNR");
+    for depth in &analysis.depths {
         println!(
-            "✅ Image dimensions: {}x{}, Max capacity: {} bytes, \
-            Message length: {} bytes, Algorithm: {:?}",
-            analysis.image_dimensions.0,
-            analysis.image_dimensions.1,
-            analysis.max_capacity_bytes,
-            analysis.input_text_len,
-            algo
+            "\t{:>12} | {:>16} | {:>4} | {:>10.2} dB",
+            depth.bits_per_channel,
+            depth.max_capacity_bytes,
+            if depth.can_fit { "yes" } else { "no" },
+            depth.expected_psnr
         );
-        
-        let plain_msg = match &args.msg {
-            Some(m) => m,
-            None => {
-                eprintln!("Error: --msg is required in encrypt mode");
-                std::process::exit(1);
-            }
-        };
-        let encrypted = match encrypt_message(plain_msg, args.key.as_deref().unwrap_or(""), algo) {
-            Ok(msg) => msg,
-            Err(e) => {
-                eprintln!("Encryption failed: {e}");
-                std::process::exit(1);
-            }
-        };
+    }
+}
 
-                if let Err(e) = stegano::embed::embed_message(
-            &img,
-            &encrypted,
-            args.prng,
-            seed,
-            &args.out,
-        ) {
-            eprintln!("Embedding failed: {e}");
-            std::process::exit(1);
-        }
+fn inspect(args: InspectArgs) {
+    let seed = args.order.seed(args.key.as_deref().unwrap_or(""), args.legacy_seed)
+        .unwrap_or_else(|e| fail("Error", &e));
+    let jpeg = read_jpeg(&args.img);
+    let header = match &jpeg {
+        Some(data) => stegano::jpeg::read_jpeg_header(data, args.order.uses_prng(), seed),
+        None => stegano::extract::read_header_with_options(
+            &open_image(&args.img),
+            &ExtractOptions { legacy_order: args.legacy_order, ..ExtractOptions::new(args.order.uses_prng(), seed) },
+        ),
+    }
+    .unwrap_or_else(|e| fail("Inspection failed", &e));
 
-        println!("✅ Message embedded into {}", args.out);
-    } else if args.decrypt.is_some() {
-        // Decrypt mode
-        let stego_img = image::open(&args.img).unwrap_or_else(|e| {
-            eprintln!("Failed to open stego image: {e}");
-            std::process::exit(1);
-        });
+    println!(
+        "\nContainer Header:\n\
+        \tVersion: {}, \
+        \n\tAlgorithm: {:?}, \
+        \n\tKDF: {:?}, \
+        \n\tCompression: {:?}, \
+        \n\tError correction: {:?}, \
+        \n\tPRNG order: {}, \
+        \n\tFile payload: {}, \
+        \n\tBits per channel: {}, \
+        \n\tChannels: {:?}, \
+        \n\tPayload length: {} bytes, \
+        \n\tCRC32: {:08x}",
 
-        let extracted = stegano::extract::extract_message(
-            &stego_img,
-            args.prng,
-            seed,
-        ).unwrap_or_else(|e| {
-            eprintln!("Extraction failed: {e}");
-            std::process::exit(1);
-        });
+        header.version,
+        header.algorithm,
+        header.kdf,
+        header.compression,
+        header.ecc,
+        match (header.uses_prng(), header.uses_feistel()) {
+            (false, _) => "none",
+            (true, true) => "feistel",
+            (true, false) => "shuffle (legacy)",
+        },
+        header.is_file(),
+        header.depth(),
+        header.channels(),
+        header.payload_len,
+        header.checksum
+    );
+    if jpeg.is_some() {
+        // The depth field of a JPEG container holds the matrix encoding
+        let k = header.depth();
+        println!("\tDCT embedding: matrix encoding (1, {}, {k})", (1 << k) - 1);
+    }
+}
 
-        println!("🕵️ Extracted (raw): {extracted}");
+fn detect(args: DetectArgs) {
+    let seed = args.order.seed(args.key.as_deref().unwrap_or(""), args.legacy_seed)
+        .unwrap_or_else(|e| fail("Error", &e));
+    let options = ExtractOptions { legacy_order: args.legacy_order, ..ExtractOptions::new(args.order.uses_prng(), seed) };
+    let detection = stegano::detect::detect(&open_image(&args.img), &options);
 
-        let final_msg = match decrypt_message(&extracted, args.key.as_deref().unwrap_or(""), algo) {
-            Ok(msg) => msg,
-            Err(e) => {
-                eprintln!("Decryption failed: {e}");
-                std::process::exit(1);
-            }
-        };
+    match &detection.header {
+        Some(header) => println!(
+            "🚩 Container header found: {:?}, {} bytes of payload",
+            header.algorithm, header.payload_len
+        ),
+        None => println!("🔎 No container header found"),
+    }
+    println!(
+        "📈 Chi-square: {:.2}, LSB embedding probability: {:.1}%",
+        detection.chi_square,
+        detection.embedding_probability * 100.0
+    );
+    println!(
+        "{}",
+        if detection.is_suspicious() { "⚠️ Image likely carries hidden data" } else { "✅ No sign of hidden data" }
+    );
+}
 
-        println!("📩 Final message: {final_msg}");
+fn open_image(path: &str) -> image::DynamicImage {
+    image::open(path).unwrap_or_else(|e| fail("Failed to open image", &Error::Io(e.to_string())))
+}
 
-    } else {
-        eprintln!("❌ Either --encrypt or --decrypt must be provided.");
-        std::process::exit(1);
+fn read_file(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| fail(&format!("Failed to read {path}"), &Error::Io(e.to_string())))
+}
+
+/// The file's bytes if it is a JPEG, whose payload lives in DCT coefficients rather than pixels
+fn read_jpeg(path: &str) -> Option<Vec<u8>> {
+    Some(read_file(path)).filter(|data| stegano::jpeg::is_jpeg(data))
+}
+
+/// The text or file to embed; clap guarantees exactly one is given
+fn read_message(msg: &Option<String>, file: &Option<String>) -> Message {
+    match (msg, file) {
+        (_, Some(path)) => Message::from_file(path).unwrap_or_else(|e| fail("Error", &e)),
+        (Some(m), None) => Message::new(m.as_bytes()),
+        (None, None) => Message::new(Vec::new()),
     }
+}
 
+/// Print the extracted message, or write its bytes to `out_file`
+fn write_output(message: &Message, out_file: Option<&str>) {
+    if let Some(path) = out_file {
+        if let Err(e) = std::fs::write(path, &message.data) {
+            fail(&format!("Failed to write {path}"), &Error::Io(e.to_string()));
+        }
+        println!("💾 Wrote {} bytes to {path}", message.data.len());
+    } else if let Some(name) = &message.file_name {
+        println!(
+            "📎 Extracted file '{name}' ({} bytes). Use --out-file <path> to save it.",
+            message.data.len()
+        );
+    } else {
+        println!("📩 Final message: {}", String::from_utf8_lossy(&message.data));
+    }
 }
@@ -1,14 +1,25 @@
 use clap::Parser;
 use image::DynamicImage;
 
-use cli::cli::Args;
+use cli::cli::{Args, AnalyzeArgs, Command};
 use cryimg_core::{
-    crypto::{Algorithm},
-    stegano::analyze::analyze_capacity,
+    crypto::{Algorithm, kdf::KdfParams},
+    stegano::{
+        analyze::analyze_capacity,
+        channels::Channels,
+        compression::Compression,
+        ecc::Ecc,
+        embed::{embed_to_image, EmbedOptions},
+        extract::{extract_message, extract_message_corrected},
+        message::Message,
+    },
 };
 
-fn parse_args(args: &[&str]) -> Args {
-    Args::parse_from(std::iter::once("cimg").chain(args.iter().cloned()))
+fn parse_args(args: &[&str]) -> AnalyzeArgs {
+    match Args::parse_from(["cimg", "analyze"].into_iter().chain(args.iter().cloned())).command {
+        Command::Analyze(args) => args,
+        other => panic!("expected analyze, got {other:?}"),
+    }
 }
 
 #[test]
@@ -20,11 +31,9 @@ fn analyze_test_capacity_none() {
     ]);
     let img = DynamicImage::new_rgb8(10, 10); // Create a dummy image
     let message = args.msg.as_ref().unwrap();
-    let algorithm = args.algorithm().unwrap();
-    let key_val = args.key.unwrap_or_default();
-    let key = key_val.as_str();
+    let algorithm = args.encrypt;
 
-    let report = analyze_capacity(&img, message, algorithm, key).unwrap();
+    let report = analyze_capacity(&img, message.as_bytes(), algorithm, Channels::Auto, Compression::None, Ecc::None).unwrap();
 
     assert_eq!(report.image_dimensions, (10, 10));
     assert_eq!(report.max_capacity_bytes, 300 / 8_usize);
@@ -39,18 +48,15 @@ fn analyze_test_capacity_xor() {
         "--img", "test.png",
         "--msg", "Secret Message",
         "--encrypt", "xor",
-        "--key", "mysecretkey",
     ]);
-    let img = DynamicImage::new_rgb8(10, 10); // Create a dummy image
+    let img = DynamicImage::new_rgb8(12, 12); // Room for the container header
     let message = args.msg.as_ref().unwrap();
-    let algorithm = args.algorithm().unwrap();
-    let key_val = args.key.unwrap();
-    let key = key_val.as_str();
+    let algorithm = args.encrypt;
 
-    let report = analyze_capacity(&img, message, algorithm, key).unwrap();
+    let report = analyze_capacity(&img, message.as_bytes(), algorithm, Channels::Auto, Compression::None, Ecc::None).unwrap();
 
-    assert_eq!(report.image_dimensions, (10, 10));
-    assert_eq!(report.max_capacity_bytes, 300 / 8_usize);
+    assert_eq!(report.image_dimensions, (12, 12));
+    assert_eq!(report.max_capacity_bytes, 432 / 8_usize);
     assert_eq!(report.input_text_len, 14);
     assert!(report.encrypted_len.is_some());
     assert_eq!(report.algorithm, Algorithm::Xor);
@@ -62,20 +68,17 @@ fn analyze_test_capacity_aes() {
         "--img", "test.png",
         "--msg", "Secret Message",
         "--encrypt", "aes",
-        "--key", "mysecretkey",
     ]);
     let img = DynamicImage::new_rgb8(10, 10); // Create a dummy image
     let message = args.msg.as_ref().unwrap();
-    let algorithm = args.algorithm().unwrap();
-    let key_val = args.key.unwrap();
-    let key = key_val.as_str();
+    let algorithm = args.encrypt;
 
-    let report = analyze_capacity(&img, message, algorithm, key).unwrap();
+    let report = analyze_capacity(&img, message.as_bytes(), algorithm, Channels::Auto, Compression::None, Ecc::None).unwrap();
 
     assert_eq!(report.image_dimensions, (10, 10));
     assert_eq!(report.max_capacity_bytes, 300 / 8_usize);
     assert_eq!(report.input_text_len, 14);
-    assert_eq!(report.encrypted_len, Some(44));
+    assert_eq!(report.encrypted_len, Some(61)); // KDF params + salt + IV + one block
     assert_eq!(report.algorithm, Algorithm::Aes);
     assert!(!report.can_fit);
 }
@@ -85,15 +88,12 @@ fn analyze_test_capacity_aes_long_message() {
         "--img", "test.png",
         "--msg", "This is a very long message that exceeds the capacity of the image.",
         "--encrypt", "aes",
-        "--key", "mysecretkey",
     ]);
     let img = DynamicImage::new_rgb8(10, 10); // Create a dummy image
     let message = args.msg.as_ref().unwrap();
-    let algorithm = args.algorithm().unwrap();
-    let key_val = args.key.unwrap();
-    let key = key_val.as_str();
+    let algorithm = args.encrypt;
 
-    let report = analyze_capacity(&img, message, algorithm, key).unwrap();
+    let report = analyze_capacity(&img, message.as_bytes(), algorithm, Channels::Auto, Compression::None, Ecc::None).unwrap();
 
     assert_eq!(report.image_dimensions, (10, 10));
     assert_eq!(report.max_capacity_bytes, 300 / 8_usize);
@@ -108,15 +108,12 @@ fn analyze_test_capacity_xor_long_message() {
         "--img", "test.png",
         "--msg", "This is a very long message that exceeds the capacity of the image.",
         "--encrypt", "xor",
-        "--key", "mysecretkey",
     ]);
     let img = DynamicImage::new_rgb8(10, 10); // Create a dummy image
     let message = args.msg.as_ref().unwrap();
-    let algorithm = args.algorithm().unwrap();
-    let key_val = args.key.unwrap();
-    let key = key_val.as_str();
+    let algorithm = args.encrypt;
 
-    let report = analyze_capacity(&img, message, algorithm, key).unwrap();
+    let report = analyze_capacity(&img, message.as_bytes(), algorithm, Channels::Auto, Compression::None, Ecc::None).unwrap();
 
     assert_eq!(report.image_dimensions, (10, 10));
     assert_eq!(report.max_capacity_bytes, 300 / 8_usize);
@@ -124,4 +121,100 @@ fn analyze_test_capacity_xor_long_message() {
     assert!(report.encrypted_len.is_some());
     assert_eq!(report.algorithm, Algorithm::Xor);
     assert!(!report.can_fit);
-}
\ No newline at end of file
+}
+
+#[test]
+fn analyze_test_capacity_per_depth() {
+    let args = parse_args(&[
+        "--img", "test.png",
+        "--msg", "This is a very long message that exceeds the capacity of the image.",
+        "--encrypt", "none",
+    ]);
+    let img = DynamicImage::new_rgb8(10, 10); // 300 channels
+    let message = args.msg.as_ref().unwrap();
+
+    let report = analyze_capacity(&img, message.as_bytes(), args.encrypt, Channels::Auto, Compression::None, Ecc::None).unwrap();
+
+    let capacities: Vec<usize> = report.depths.iter().map(|d| d.max_capacity_bytes).collect();
+    // 21 header bytes in 168 channels, then 132 channels at 1-4 bits each
+    assert_eq!(capacities, [37, 54, 70, 87]);
+    assert_eq!(report.depths[0].max_capacity_bytes, report.max_capacity_bytes);
+    assert_eq!(report.total_payload_bytes, 88);
+    assert!(report.depths.iter().all(|d| !d.can_fit));
+
+    let short = analyze_capacity(&img, b"Short", args.encrypt, Channels::Auto, Compression::None, Ecc::None).unwrap();
+    assert!(short.depths.iter().all(|d| d.can_fit));
+    assert!(short.depths.windows(2).all(|w| w[0].expected_psnr > w[1].expected_psnr));
+}
+
+#[test]
+fn analyze_test_can_fit_matches_embed() {
+    let img = DynamicImage::new_rgba8(9, 7);
+    for (algorithm, key) in [(Algorithm::None, ""), (Algorithm::Xor, "k"), (Algorithm::AesGcm, "k")] {
+        for channels in [Channels::Auto, Channels::Rgba, Channels::Green] {
+            for depth in 1..=4 {
+                let options = EmbedOptions {
+                    algorithm,
+                    kdf: KdfParams::fastest(),
+                    depth,
+                    channels,
+                    ..Default::default()
+                };
+                // Walk up to the first message that no longer fits
+                for len in 0.. {
+                    let message = Message::new(vec![b'x'; len]);
+                    let report = analyze_capacity(&img, &message.encode(), algorithm, channels, Compression::None, Ecc::None).unwrap();
+                    let fits = report.depths[depth as usize - 1].can_fit;
+                    let embedded = embed_to_image(&img, &message, key, &options);
+                    assert_eq!(fits, embedded.is_ok(), "{algorithm:?} {channels:?} {depth} {len}");
+                    if !fits {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn analyze_test_compression() {
+    let img = DynamicImage::new_rgb8(20, 20); // 1200 channels, 129 payload bytes
+    let message = "all work and no play makes jack a dull boy. ".repeat(10);
+
+    let raw = analyze_capacity(&img, message.as_bytes(), Algorithm::None, Channels::Auto, Compression::None, Ecc::None).unwrap();
+    assert_eq!(raw.compressed_len, None);
+    assert!(!raw.can_fit);
+
+    let auto = analyze_capacity(&img, message.as_bytes(), Algorithm::None, Channels::Auto, Compression::Auto, Ecc::None).unwrap();
+    assert_ne!(auto.compression, Compression::None);
+    assert_eq!(auto.total_payload_bytes, 21 + auto.compressed_len.unwrap());
+    assert!(auto.can_fit);
+
+    let options = EmbedOptions { compression: auto.compression, ..Default::default() };
+    let stego = embed_to_image(&img, &Message::new(message.as_bytes()), "", &options).unwrap();
+    let (header, extracted) = extract_message(&stego, "", false, None).unwrap();
+    assert_eq!(header.compression, auto.compression);
+    assert_eq!(extracted.data, message.as_bytes());
+}
+
+#[test]
+fn analyze_test_ecc() {
+    let img = DynamicImage::new_rgb8(40, 40); // 4800 channels
+    let message = vec![b'x'; 400];
+
+    let plain = analyze_capacity(&img, &message, Algorithm::None, Channels::Auto, Compression::None, Ecc::None).unwrap();
+    assert_eq!(plain.ecc_overhead_bytes, 0);
+
+    // Header parity, then 2 codewords of 32 parity bytes for 400 bytes of data
+    let report = analyze_capacity(&img, &message, Algorithm::None, Channels::Auto, Compression::None, Ecc::High).unwrap();
+    assert_eq!(report.ecc_overhead_bytes, 16 + 2 * 32);
+    assert_eq!(report.total_payload_bytes, plain.total_payload_bytes + 80);
+    assert_eq!(report.max_capacity_bytes, plain.max_capacity_bytes);
+    assert!(report.can_fit);
+
+    let options = EmbedOptions { ecc: Ecc::High, ..Default::default() };
+    let stego = embed_to_image(&img, &Message::new(message.clone()), "", &options).unwrap();
+    let (header, extracted, corrected) = extract_message_corrected(&stego, "", false, None).unwrap();
+    assert_eq!((header.ecc, corrected), (Ecc::High, 0));
+    assert_eq!(extracted.data, message);
+}
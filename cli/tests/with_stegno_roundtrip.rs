@@ -0,0 +1,283 @@
+use std::io::Cursor;
+
+use image::{ColorType, DynamicImage, ImageBuffer, Rgba};
+use proptest::prelude::*;
+use rand_chacha::{ChaCha8Rng, rand_core::{RngCore, SeedableRng}};
+
+use cryimg_core::{
+    Error,
+    crypto::{Algorithm, kdf::KdfParams},
+    stegano::{
+        analyze::analyze_capacity,
+        channels::Channels,
+        compression::Compression,
+        ecc::Ecc,
+        embed::{embed_to_image, EmbedOptions, EmbedStrategy},
+        extract::extract_message,
+        header::{HEADER_LEN, MAX_DEPTH},
+        message::Message,
+        output::{write_image, LosslessFormat},
+    },
+    utils::Seed,
+};
+
+/// Cover layouts `image` can decode; every one must survive a round trip
+#[derive(Debug, Clone, Copy)]
+enum Cover {
+    Luma8,
+    LumaA8,
+    Rgb8,
+    Rgba8,
+    /// RGBA with some transparent pixels, so `Auto` writes into alpha
+    Translucent,
+    Rgb16,
+}
+
+fn cover(kind: Cover, width: u32, height: u32, noise: u64) -> DynamicImage {
+    let mut rng = ChaCha8Rng::seed_from_u64(noise);
+    let rgba = ImageBuffer::from_fn(width, height, |_, _| {
+        let [r, g, b, a] = rng.next_u32().to_le_bytes();
+        Rgba([r, g, b, if matches!(kind, Cover::Translucent) { a } else { 255 }])
+    });
+    let img = DynamicImage::ImageRgba8(rgba);
+    match kind {
+        Cover::Luma8 => DynamicImage::ImageLuma8(img.to_luma8()),
+        Cover::LumaA8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        Cover::Rgb8 => DynamicImage::ImageRgb8(img.to_rgb8()),
+        Cover::Rgba8 | Cover::Translucent => img,
+        Cover::Rgb16 => DynamicImage::ImageRgb16(img.to_rgb16()),
+    }
+}
+
+fn any_cover() -> impl Strategy<Value = Cover> {
+    prop_oneof![
+        Just(Cover::Luma8),
+        Just(Cover::LumaA8),
+        Just(Cover::Rgb8),
+        Just(Cover::Rgba8),
+        Just(Cover::Translucent),
+        Just(Cover::Rgb16),
+    ]
+}
+
+fn any_algorithm() -> impl Strategy<Value = Algorithm> {
+    prop_oneof![
+        Just(Algorithm::None),
+        Just(Algorithm::Xor),
+        Just(Algorithm::Caesar),
+        Just(Algorithm::Rot13),
+        Just(Algorithm::Aes),
+        Just(Algorithm::AesGcm),
+        Just(Algorithm::ChaCha20Poly1305),
+    ]
+}
+
+fn any_channels() -> impl Strategy<Value = Channels> {
+    prop_oneof![
+        Just(Channels::Auto),
+        Just(Channels::Rgb),
+        Just(Channels::Rgba),
+        Just(Channels::Red),
+        Just(Channels::Green),
+        Just(Channels::Blue),
+    ]
+}
+
+/// Everything that changes how a message is embedded
+#[derive(Debug, Clone)]
+struct Mode {
+    algorithm: Algorithm,
+    seed: Option<String>,
+    strategy: EmbedStrategy,
+    depth: u8,
+    channels: Channels,
+    compression: Compression,
+    ecc: Ecc,
+}
+
+impl Mode {
+    /// Caesar wants a number, everything else any non-empty key
+    fn key(&self) -> &'static str {
+        if self.algorithm == Algorithm::Caesar { "7" } else { "correct horse" }
+    }
+
+    fn options(&self) -> EmbedOptions {
+        EmbedOptions {
+            algorithm: self.algorithm,
+            // Ciphertext sizes do not depend on the KDF
+            kdf: KdfParams::fastest(),
+            use_prng: self.seed.is_some(),
+            seed: self.seed.as_deref().map(Seed::new),
+            strategy: self.strategy,
+            depth: self.depth,
+            channels: self.channels,
+            compression: self.compression,
+            ecc: self.ecc,
+        }
+    }
+}
+
+fn any_mode() -> impl Strategy<Value = Mode> {
+    (
+        any_algorithm(),
+        proptest::option::of("[a-z0-9]{1,8}"),
+        prop_oneof![Just(EmbedStrategy::Replace), Just(EmbedStrategy::Match)],
+        1..=MAX_DEPTH,
+        any_channels(),
+        prop_oneof![
+            Just(Compression::Auto),
+            Just(Compression::None),
+            Just(Compression::Deflate),
+            Just(Compression::Zstd),
+            Just(Compression::Brotli),
+        ],
+        prop_oneof![Just(Ecc::None), Just(Ecc::Low), Just(Ecc::Max)],
+    )
+        .prop_map(|(algorithm, seed, strategy, depth, channels, compression, ecc)| {
+            Mode { algorithm, seed, strategy, depth, channels, compression, ecc }
+        })
+}
+
+/// Embed, encode to PNG and back, then extract; `None` when the message does not fit
+fn roundtrip(img: &DynamicImage, message: &Message, mode: &Mode) -> Result<Option<Message>, TestCaseError> {
+    let report = analyze_capacity(img, &message.encode(), mode.algorithm, mode.channels, mode.compression, mode.ecc).unwrap();
+    let fits = report.depths[mode.depth as usize - 1].can_fit;
+
+    let stego = match embed_to_image(img, message, mode.key(), &mode.options()) {
+        Ok(stego) => stego,
+        Err(Error::CapacityExceeded { .. }) => {
+            prop_assert!(!fits, "analyze said it fits, but embedding ran out of room");
+            return Ok(None);
+        }
+        Err(e) => return Err(TestCaseError::fail(format!("embedding failed: {e}"))),
+    };
+    prop_assert!(fits, "analyze said it does not fit, but embedding succeeded");
+    prop_assert_eq!(stego.color(), expected_color(img, mode));
+
+    let mut png = Cursor::new(Vec::new());
+    write_image(&stego, &mut png, LosslessFormat::Png).unwrap();
+    let reloaded = image::load_from_memory(png.get_ref()).unwrap();
+    prop_assert_eq!(reloaded.color(), stego.color());
+
+    let seed = mode.seed.as_deref().map(Seed::new);
+    let (header, extracted) = extract_message(&reloaded, mode.key(), seed.is_some(), seed)
+        .map_err(|e| TestCaseError::fail(format!("extraction failed: {e}")))?;
+    prop_assert_eq!(header.algorithm, mode.algorithm);
+    prop_assert_eq!(header.depth(), mode.depth);
+    Ok(Some(extracted))
+}
+
+/// The cover's color type at 8 bits, with alpha only added when the payload went into it
+fn expected_color(img: &DynamicImage, mode: &Mode) -> ColorType {
+    let alpha = img.color().has_alpha() || mode.channels == Channels::Rgba;
+    match (img.color().has_color(), alpha) {
+        (false, false) => ColorType::L8,
+        (false, true) => ColorType::La8,
+        (true, false) => ColorType::Rgb8,
+        (true, true) => ColorType::Rgba8,
+    }
+}
+
+/// Longest plain message of `mode` that still fits in `img`
+fn max_message_len(img: &DynamicImage, mode: &Mode) -> Option<usize> {
+    let depth = mode.depth as usize - 1;
+    let fits = |len: usize| analyze_capacity(img, &vec![0; len], mode.algorithm, mode.channels, mode.compression, mode.ecc).unwrap().depths[depth].can_fit;
+    // Ciphers only ever add bytes, so nothing longer than the raw capacity fits
+    let capacity = analyze_capacity(img, &[], mode.algorithm, mode.channels, mode.compression, mode.ecc).unwrap().depths[depth].max_capacity_bytes;
+    (0..=capacity.saturating_sub(HEADER_LEN)).rev().find(|&len| fits(len))
+}
+
+/// Header bytes, with the 16 bytes of parity error correction puts after it
+fn expected_header_len(mode: &Mode) -> usize {
+    if mode.ecc == Ecc::None { HEADER_LEN } else { HEADER_LEN + 16 }
+}
+
+/// Container bytes for a message of `len` bytes: header, then the ciphertext cut
+/// into 255-byte Reed-Solomon codewords with the mode's parity in each
+fn expected_container_len(mode: &Mode, len: usize) -> usize {
+    let ciphertext = mode.algorithm.ciphertext_len(len);
+    let parity = mode.ecc.parity();
+    let codewords = if parity == 0 { 0 } else { ciphertext.div_ceil(255 - parity) };
+    expected_header_len(mode) + ciphertext + codewords * parity
+}
+
+/// Container bytes that fit, worked out from first principles rather than the
+/// library's layout: header bits one per channel, then `depth` bits per channel
+fn expected_capacity(kind: Cover, width: u32, height: u32, mode: &Mode) -> usize {
+    // Grayscale covers carry bits in their gray level, plus alpha where the selection includes it
+    let gray = matches!(kind, Cover::Luma8 | Cover::LumaA8);
+    let per_pixel = match mode.channels {
+        Channels::Rgba | Channels::LumaAlpha if gray => 2,
+        _ if gray => 1,
+        Channels::Auto if matches!(kind, Cover::Translucent) => 4,
+        Channels::Auto | Channels::Rgb => 3,
+        Channels::Rgba => 4,
+        Channels::Red | Channels::Green | Channels::Blue | Channels::Luma => 1,
+        Channels::LumaAlpha => 2,
+    };
+    let channels = (width * height) as usize * per_pixel;
+    let header_len = expected_header_len(mode);
+    match channels.checked_sub(header_len * 8) {
+        Some(rest) => header_len + rest * mode.depth as usize / 8,
+        None => 0,
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(96))]
+
+    #[test]
+    fn roundtrip_random_messages(
+        kind in any_cover(),
+        width in 1u32..48,
+        height in 1u32..48,
+        noise in any::<u64>(),
+        mode in any_mode(),
+        pattern in proptest::collection::vec(any::<u8>(), 0..100),
+        repeats in 1usize..12,
+        file_name in proptest::option::of("[a-z]{1,12}\\.bin"),
+    ) {
+        let img = cover(kind, width, height, noise);
+        // Repeated patterns give the codecs something to compress
+        let message = Message { data: pattern.repeat(repeats), file_name };
+        if let Some(extracted) = roundtrip(&img, &message, &mode)? {
+            prop_assert_eq!(extracted, message);
+        }
+    }
+
+    #[test]
+    fn roundtrip_exact_capacity(
+        kind in any_cover(),
+        width in 8u32..40,
+        height in 8u32..40,
+        noise in any::<u64>(),
+        mode in any_mode(),
+    ) {
+        // Random data does not compress, and the capacity search below measures zeros
+        let mode = Mode { compression: Compression::None, ..mode };
+        let img = cover(kind, width, height, noise);
+        let capacity = expected_capacity(kind, width, height, &mode);
+        let Some(max_len) = max_message_len(&img, &mode) else {
+            // Not even the header fits
+            prop_assert!(expected_container_len(&mode, 0) > capacity);
+            prop_assert!(embed_to_image(&img, &Message::new(Vec::new()), mode.key(), &mode.options()).is_err());
+            return Ok(());
+        };
+
+        // The longest message that fits comes back intact...
+        prop_assert!(expected_container_len(&mode, max_len) <= capacity);
+        prop_assert!(expected_container_len(&mode, max_len + 1) > capacity);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(noise);
+        let mut data = vec![0; max_len];
+        rng.fill_bytes(&mut data);
+        let message = Message::new(data.clone());
+        let extracted = roundtrip(&img, &message, &mode)?;
+        prop_assert_eq!(extracted, Some(message));
+
+        // ...and one more byte is rejected
+        data.push(0);
+        let longer = Message::new(data);
+        prop_assert_eq!(roundtrip(&img, &longer, &mode)?, None);
+    }
+}
@@ -1,36 +1,35 @@
 use aes::Aes256;
 use block_modes::{Cbc, BlockMode};
 use block_modes::block_padding::Pkcs7;
-use sha2::{Sha256, Digest};
-use base64::{engine::general_purpose, Engine as _};
+use aes_gcm::{Aes256Gcm, aead::{Aead, AeadCore, KeyInit, Nonce, generic_array::typenum::Unsigned}};
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::Error;
+use super::kdf::{open_key, sha256_key};
 
 
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 
 
-pub fn xor_decrypt(encoded: &str, key: &str) -> Result<String, String> {
-    let encrypted = general_purpose::STANDARD
-        .decode(encoded)
-        .map_err(|_| "Failed to decode base64")?;
+pub fn xor_decrypt(encrypted: &[u8], key: &str) -> Result<Vec<u8>, Error> {
     if key.is_empty() {
-        return Err("XOR decryption requires a non-empty key".into());
+        return Err(Error::BadKey("XOR decryption requires a non-empty key".into()));
     }
-    let decrypted: Vec<u8> = encrypted
-        .into_iter()
+    Ok(encrypted
+        .iter()
         .zip(key.bytes().cycle())
         .map(|(m, k)| m ^ k)
-        .collect();
-    String::from_utf8(decrypted).map_err(|_| "Invalid UTF-8 in decrypted XOR message".into())
+        .collect())
 }
 
-pub fn caesar_decrypt(msg: &str, key: &str) -> Result<String, String> {
-    let shift = key.parse::<u8>().map_err(|_| "Key must be a number for Caesar cipher")?;
-    let decrypted: String = msg
-        .chars()
-        .map(|c| {
+pub fn caesar_decrypt(msg: &[u8], key: &str) -> Result<Vec<u8>, Error> {
+    let shift = key.parse::<u8>().map_err(|_| Error::BadKey("Key must be a number for Caesar cipher".into()))?;
+    let decrypted: Vec<u8> = msg
+        .iter()
+        .map(|&c| {
             if c.is_ascii_alphabetic() {
                 let base = if c.is_ascii_lowercase() { b'a' } else { b'A' };
-                ((c as u8 - base + 26 - shift) % 26 + base) as char
+                (c - base + 26 - shift % 26) % 26 + base
             } else {
                 c
             }
@@ -39,49 +38,89 @@ pub fn caesar_decrypt(msg: &str, key: &str) -> Result<String, String> {
     Ok(decrypted)
 }
 
-pub fn rot13_decrypt(msg: &str) -> Result<String, String> {
+pub fn rot13_decrypt(msg: &[u8]) -> Result<Vec<u8>, Error> {
     super::encrypt::rot13_encrypt(msg)
 }
 
-pub fn aes_decrypt(encoded: &str, key: &str) -> Result<String, String> {
+pub fn aes_decrypt(data: &[u8], key: &str) -> Result<Vec<u8>, Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("AES decryption requires a non-empty key".into()));
+    }
+
+    // Re-derive the key from the stored KDF params and salt
+    let (key_bytes, rest) = open_key(key, data)?;
+    aes_cbc_decrypt(&key_bytes, rest)
+}
+
+/// Decrypt AES output from before salted KDFs: IV + ciphertext, key = SHA-256(key)
+pub fn legacy_aes_decrypt(data: &[u8], key: &str) -> Result<Vec<u8>, Error> {
     if key.is_empty() {
-        return Err("AES decryption requires a non-empty key".into());
+        return Err(Error::BadKey("AES decryption requires a non-empty key".into()));
     }
 
-    let data = general_purpose::STANDARD
-        .decode(encoded)
-        .map_err(|e| format!("Base64 decode error: {e}"))?;
+    aes_cbc_decrypt(&sha256_key(key), data)
+}
 
+fn aes_cbc_decrypt(key_bytes: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
     if data.len() < 16 {
-        return Err("Data too short to contain IV".into());
+        return Err(Error::Decryption("Data too short to contain IV".into()));
     }
 
     let (iv, ciphertext) = data.split_at(16);
 
-    // Derive the key
-    let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
-    let key_bytes = hasher.finalize();
-
     // Create cipher
-    let cipher = Aes256Cbc::new_from_slices(&key_bytes, iv)
-        .map_err(|e| format!("Cipher creation error: {e}"))?;
+    let cipher = Aes256Cbc::new_from_slices(key_bytes, iv)
+        .map_err(|e| Error::Decryption(format!("Cipher creation error: {e}")))?;
 
-    let decrypted = cipher
+    cipher
         .decrypt_vec(ciphertext)
-        .map_err(|e| format!("Decryption failed: {e}"))?;
+        .map_err(|e| Error::Decryption(format!("Decryption failed: {e}")))
+}
 
-    String::from_utf8(decrypted).map_err(|e| format!("UTF-8 error: {e}"))
+pub fn aes_gcm_decrypt(data: &[u8], key: &str) -> Result<Vec<u8>, Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("AES-GCM decryption requires a non-empty key".into()));
+    }
+    let (key_bytes, rest) = open_key(key, data)?;
+    aead_decrypt(&Aes256Gcm::new(&key_bytes.into()), rest)
 }
 
+pub fn chacha20_poly1305_decrypt(data: &[u8], key: &str) -> Result<Vec<u8>, Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("ChaCha20-Poly1305 decryption requires a non-empty key".into()));
+    }
+    let (key_bytes, rest) = open_key(key, data)?;
+    aead_decrypt(&ChaCha20Poly1305::new(&key_bytes.into()), rest)
+}
+
+/// Split off the nonce and verify the tag; any mismatch is an authentication failure
+fn aead_decrypt<C: Aead>(cipher: &C, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce_len = <C as AeadCore>::NonceSize::USIZE;
+    if data.len() < nonce_len {
+        return Err(Error::Decryption("Data too short to contain nonce".into()));
+    }
+    let (nonce, ciphertext) = data.split_at(nonce_len);
+
+    cipher
+        .decrypt(Nonce::<C>::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Authentication)
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::dearmor;
     use crate::crypto::encrypt::*;
+    use crate::crypto::kdf::{Kdf, KdfParams, KdfPreset};
+
+    fn fast_kdf() -> KdfParams {
+        KdfParams::new(Kdf::Argon2id, KdfPreset::Interactive)
+    }
 
     #[test]
     fn decrypt_xor_decrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "key";
         let encrypted = xor_encrypt(msg, key).unwrap();
         let decrypted = xor_decrypt(&encrypted, key).unwrap();
@@ -89,41 +128,62 @@ mod tests {
     }
     #[test]
     fn decrypt_caesar_decrypt() {
-        let msg = "Khoor, Zruog!";
+        let msg = b"Khoor, Zruog!";
         let key = "3";
         let decrypted = caesar_decrypt(msg, key).unwrap();
-        assert_eq!(decrypted, "Hello, World!");
+        assert_eq!(decrypted, b"Hello, World!");
     }
     #[test]
     fn decrypt_rot13_decrypt() {
-        let msg = "Uryyb, Jbeyq!";
+        let msg = b"Uryyb, Jbeyq!";
         let decrypted = rot13_decrypt(msg).unwrap();
-        assert_eq!(decrypted, "Hello, World!");
+        assert_eq!(decrypted, b"Hello, World!");
     }
     #[test]
     fn decrypt_aes_decrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "mysecretkey";
-        let encrypted = aes_encrypt(msg, key).unwrap();
+        let encrypted = aes_encrypt(msg, key, &fast_kdf()).unwrap();
         let decrypted = aes_decrypt(&encrypted, key).unwrap();
         assert_eq!(decrypted, msg);
     }
     #[test]
+    fn decrypt_aes_gcm_wrong_key() {
+        let encrypted = aes_gcm_encrypt(b"Hello, World!", "mysecretkey", &fast_kdf()).unwrap();
+        let result = aes_gcm_decrypt(&encrypted, "wrongkey");
+        assert_eq!(result.unwrap_err(), Error::Authentication);
+    }
+    #[test]
+    fn decrypt_chacha20_poly1305_tampered() {
+        let encrypted = chacha20_poly1305_encrypt(b"Hello, World!", "mysecretkey", &fast_kdf()).unwrap();
+        let mut tampered = encrypted;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let result = chacha20_poly1305_decrypt(&tampered, "mysecretkey");
+        assert_eq!(result.unwrap_err(), Error::Authentication);
+    }
+    #[test]
+    fn decrypt_legacy_aes_decrypt() {
+        // Produced by the unsalted SHA-256 AES of earlier releases
+        let encrypted = dearmor(b"AAECAwQFBgcICQoLDA0OD2MmIH3qbXCvWg+i/Non/sI=").unwrap();
+        assert_eq!(legacy_aes_decrypt(&encrypted, "mysecretkey").unwrap(), b"Hello, World!");
+    }
+    #[test]
     fn decrypt_xor_decrypt_empty_key() {
-        let msg = "Hello, World!";
-        let encrypted = aes_encrypt(msg, "mysecretkey").unwrap();
+        let msg = b"Hello, World!";
+        let encrypted = aes_encrypt(msg, "mysecretkey", &fast_kdf()).unwrap();
         let key = "";
         let result = xor_decrypt(&encrypted, key);
         assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), "XOR decryption requires a non-empty key");
+        assert_eq!(result.unwrap_err(), Error::BadKey("XOR decryption requires a non-empty key".into()));
     }
     #[test]
     fn decrypt_caesar_decrypt_invalid_key() {
-        let msg = "Khoor, Zruog!";
+        let msg = b"Khoor, Zruog!";
         let key = "invalid";
         let result = caesar_decrypt(msg, key);
         assert!(result.is_err());
-        assert_eq!(result.err().unwrap(), "Key must be a number for Caesar cipher");
+        assert_eq!(result.err().unwrap(), Error::BadKey("Key must be a number for Caesar cipher".into()));
     }
 
 }
\ No newline at end of file
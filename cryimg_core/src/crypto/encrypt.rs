@@ -1,32 +1,35 @@
 use aes::Aes256;
 use block_modes::{Cbc, block_padding::Pkcs7, BlockMode};
-use sha2::{Sha256, Digest};
 use rand::prelude::*;
-use base64::{engine::general_purpose, Engine as _};
+use aes_gcm::{Aes256Gcm, aead::{Aead, KeyInit, Nonce}};
+use chacha20poly1305::ChaCha20Poly1305;
+
+use crate::Error;
+use super::kdf::{seal_key, KdfParams};
 
 
 type Aes256Cbc = Cbc<Aes256, Pkcs7>;
 
-pub fn xor_encrypt(msg: &str, key: &str) -> Result<String, String> {
+pub fn xor_encrypt(msg: &[u8], key: &str) -> Result<Vec<u8>, Error> {
     if key.is_empty() {
-        return Err("XOR encryption requires a non-empty key".into());
+        return Err(Error::BadKey("XOR encryption requires a non-empty key".into()));
     }
-    let encrypted: Vec<u8> = msg
-        .bytes()
+    Ok(msg
+        .iter()
         .zip(key.bytes().cycle())
         .map(|(m, k)| m ^ k)
-        .collect();
-    Ok(general_purpose::STANDARD.encode(&encrypted))
+        .collect())
 }
 
-pub fn caesar_encrypt(msg: &str, key: &str) -> Result<String, String> {
-    let shift = key.parse::<u8>().map_err(|_| "Key must be a number for Caesar cipher")?;
-    let encrypted: String = msg
-        .chars()
-        .map(|c| {
+/// Shifts ASCII letters only; every other byte passes through unchanged
+pub fn caesar_encrypt(msg: &[u8], key: &str) -> Result<Vec<u8>, Error> {
+    let shift = key.parse::<u8>().map_err(|_| Error::BadKey("Key must be a number for Caesar cipher".into()))?;
+    let encrypted: Vec<u8> = msg
+        .iter()
+        .map(|&c| {
             if c.is_ascii_alphabetic() {
                 let base = if c.is_ascii_lowercase() { b'a' } else { b'A' };
-                ((c as u8 - base + shift) % 26 + base) as char
+                (c - base + shift % 26) % 26 + base
             } else {
                 c
             }
@@ -35,25 +38,22 @@ pub fn caesar_encrypt(msg: &str, key: &str) -> Result<String, String> {
     Ok(encrypted)
 }
 
-pub fn rot13_encrypt(msg: &str) -> Result<String, String> {
-    msg.chars()
-        .map(|c| match c {
-            'a'..='z' => Ok((((c as u8 - b'a' + 13) % 26) + b'a') as char),
-            'A'..='Z' => Ok((((c as u8 - b'A' + 13) % 26) + b'A') as char),
+pub fn rot13_encrypt(msg: &[u8]) -> Result<Vec<u8>, Error> {
+    msg.iter()
+        .map(|&c| match c {
+            b'a'..=b'z' => Ok(((c - b'a' + 13) % 26) + b'a'),
+            b'A'..=b'Z' => Ok(((c - b'A' + 13) % 26) + b'A'),
             _ => Ok(c),
         })
         .collect()
 }
 
-pub fn aes_encrypt(msg: &str, key: &str) -> Result<String, String> {
+pub fn aes_encrypt(msg: &[u8], key: &str, kdf: &KdfParams) -> Result<Vec<u8>, Error> {
     if key.is_empty() {
-        return Err("AES encryption requires a non-empty key".into());
+        return Err(Error::BadKey("AES encryption requires a non-empty key".into()));
     }
 
-    // Derive a 256-bit key from the string using SHA-256
-    let mut hasher = Sha256::new();
-    hasher.update(key.as_bytes());
-    let key_bytes = hasher.finalize();
+    let (key_bytes, mut result) = seal_key(key, kdf)?;
 
     // Generate a random 16-byte IV
     let mut rng = rand::rng();
@@ -61,29 +61,65 @@ pub fn aes_encrypt(msg: &str, key: &str) -> Result<String, String> {
 
     // Create AES CBC cipher
     let cipher = Aes256Cbc::new_from_slices(&key_bytes, &iv)
-        .map_err(|e| format!("Cipher creation error: {e}"))?;
+        .map_err(|e| Error::InvalidArgument(format!("Cipher creation error: {e}")))?;
 
     // Encrypt message
-    let ciphertext = cipher.encrypt_vec(msg.as_bytes());
+    let ciphertext = cipher.encrypt_vec(msg);
 
-    // Combine IV + ciphertext and base64 encode
-    let mut result = Vec::new();
+    // Combine KDF params + salt + IV + ciphertext
     result.extend_from_slice(&iv);
     result.extend_from_slice(&ciphertext);
 
-    Ok(general_purpose::STANDARD.encode(&result))
+    Ok(result)
+}
+
+
+pub fn aes_gcm_encrypt(msg: &[u8], key: &str, kdf: &KdfParams) -> Result<Vec<u8>, Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("AES-GCM encryption requires a non-empty key".into()));
+    }
+    let (key_bytes, prefix) = seal_key(key, kdf)?;
+    aead_encrypt(&Aes256Gcm::new(&key_bytes.into()), prefix, msg)
+}
+
+pub fn chacha20_poly1305_encrypt(msg: &[u8], key: &str, kdf: &KdfParams) -> Result<Vec<u8>, Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("ChaCha20-Poly1305 encryption requires a non-empty key".into()));
+    }
+    let (key_bytes, prefix) = seal_key(key, kdf)?;
+    aead_encrypt(&ChaCha20Poly1305::new(&key_bytes.into()), prefix, msg)
 }
 
+/// Encrypt under a fresh random nonce; output is prefix + nonce + ciphertext + tag
+fn aead_encrypt<C: Aead>(cipher: &C, prefix: Vec<u8>, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut nonce = Nonce::<C>::default();
+    rand::rng().fill(&mut nonce[..]);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| Error::InvalidArgument("AEAD encryption failed".into()))?;
+
+    let mut result = prefix;
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&ciphertext);
+
+    Ok(result)
+}
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::crypto::decrypt::*;
+    use crate::crypto::kdf::{Kdf, KdfPreset};
+
+    fn fast_kdf() -> KdfParams {
+        KdfParams::new(Kdf::Argon2id, KdfPreset::Interactive)
+    }
 
     #[test]
     fn encrypt_xor_encrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "key";
         let encrypted = xor_encrypt(msg, key).unwrap();
         let decrypted = xor_decrypt(&encrypted, key).unwrap();
@@ -92,7 +128,7 @@ mod tests {
 
     #[test]
     fn encrypt_caesar_encrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "3";
         let encrypted = caesar_encrypt(msg, key).unwrap();
         let decrypted = caesar_decrypt(&encrypted, key).unwrap();
@@ -101,37 +137,78 @@ mod tests {
 
     #[test]
     fn encrypt_rot13_encrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let encrypted = rot13_encrypt(msg).unwrap();
         let decrypted = rot13_decrypt(&encrypted).unwrap();
         assert_eq!(decrypted, msg);
     }
 
+    #[test]
+    fn encrypt_binary_roundtrip() {
+        let msg: Vec<u8> = (0..=255).collect();
+        let encrypted = xor_encrypt(&msg, "key").unwrap();
+        assert_eq!(xor_decrypt(&encrypted, "key").unwrap(), msg);
+        let encrypted = caesar_encrypt(&msg, "29").unwrap();
+        assert_eq!(caesar_decrypt(&encrypted, "29").unwrap(), msg);
+        let encrypted = aes_encrypt(&msg, "key", &fast_kdf()).unwrap();
+        assert_eq!(aes_decrypt(&encrypted, "key").unwrap(), msg);
+    }
+
     #[test]
     fn encrypt_xor_encrypt_empty_key() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "";
         let result = xor_encrypt(msg, key);
         assert!(result.is_err());
-        assert_eq!(result.err().unwrap(), "XOR encryption requires a non-empty key");
+        assert_eq!(result.err().unwrap(), Error::BadKey("XOR encryption requires a non-empty key".into()));
     }
 
     #[test]
     fn encrypt_caesar_encrypt_invalid_key() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "invalid";
         let result = caesar_encrypt(msg, key);
         assert!(result.is_err());
-        assert_eq!(result.err().unwrap(), "Key must be a number for Caesar cipher");
+        assert_eq!(result.err().unwrap(), Error::BadKey("Key must be a number for Caesar cipher".into()));
+    }
+
+    #[test]
+    fn encrypt_aes_gcm_encrypt() {
+        let msg = b"Hello, World!";
+        let key = "mysecretkey";
+        let encrypted = aes_gcm_encrypt(msg, key, &fast_kdf()).unwrap();
+        assert_ne!(encrypted, aes_gcm_encrypt(msg, key, &fast_kdf()).unwrap()); // fresh nonce every time
+        let decrypted = aes_gcm_decrypt(&encrypted, key).unwrap();
+        assert_eq!(decrypted, msg);
+    }
+
+    #[test]
+    fn encrypt_chacha20_poly1305_encrypt() {
+        let msg = b"Hello, World!";
+        let key = "mysecretkey";
+        let encrypted = chacha20_poly1305_encrypt(msg, key, &fast_kdf()).unwrap();
+        let decrypted = chacha20_poly1305_decrypt(&encrypted, key).unwrap();
+        assert_eq!(decrypted, msg);
     }
 
     #[test]
     fn encrypt_aes_encrypt() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "mysecretkey";
-        let encrypted = aes_encrypt(msg, key).unwrap();
+        let encrypted = aes_encrypt(msg, key, &fast_kdf()).unwrap();
         let decrypted = aes_decrypt(&encrypted, key).unwrap();
         assert_eq!(decrypted, msg);
     }
 
+    #[test]
+    fn encrypt_aes_gcm_with_each_kdf() {
+        let msg = b"Hello, World!";
+        let key = "correct horse battery staple";
+        for kdf in [Kdf::Argon2id, Kdf::Scrypt, Kdf::Pbkdf2] {
+            let params = KdfParams::new(kdf, KdfPreset::Interactive);
+            let encrypted = aes_gcm_encrypt(msg, key, &params).unwrap();
+            assert_eq!(aes_gcm_decrypt(&encrypted, key).unwrap(), msg, "{kdf:?}");
+        }
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,351 @@
+use sha2::{Sha256, Digest};
+use rand::Rng;
+
+use crate::Error;
+
+/// Length of the random salt stored in front of the ciphertext
+pub const SALT_LEN: usize = 16;
+
+/// Size of serialized [`KdfParams`]: `kdf | cost: u32 | rounds: u32 | parallelism: u32`
+pub const PARAMS_LEN: usize = 13;
+
+/// Upper bounds on stored KDF costs, a few times the sensitive presets.
+/// The parameters are read from the ciphertext, so without them a crafted
+/// image could make extraction allocate or spin for as long as it likes.
+const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const ARGON2_MAX_PASSES: u32 = 16;
+const SCRYPT_MAX_MEMORY: u64 = 1 << 30;
+const MAX_PARALLELISM: u32 = 16;
+const PBKDF2_MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Key derivation used to turn the user's key into cipher key material
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Kdf {
+    /// The key is used as given (XOR, Caesar) or not at all
+    #[value(skip)]
+    None,
+    /// Single unsalted SHA-256 of the key (images made before salted KDFs)
+    #[value(skip)]
+    Sha256,
+    /// Argon2id, memory-hard (recommended)
+    #[default]
+    Argon2id,
+    /// scrypt, memory-hard
+    Scrypt,
+    /// PBKDF2-HMAC-SHA256, for environments without memory to spare
+    Pbkdf2,
+}
+
+/// Cost presets for the salted KDFs, trading derivation time for brute-force resistance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum KdfPreset {
+    /// Fast enough for frequent use
+    Interactive,
+    #[default]
+    Moderate,
+    /// Slow; for long-lived secrets
+    Sensitive,
+}
+
+/// A KDF together with its cost parameters. The meaning of the fields depends on the KDF:
+///
+/// | KDF      | `cost`         | `rounds`     | `parallelism` |
+/// |----------|----------------|--------------|---------------|
+/// | Argon2id | memory in KiB  | passes       | lanes         |
+/// | scrypt   | log2(N)        | block size r | p             |
+/// | PBKDF2   | iterations     | unused       | unused        |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub kdf: Kdf,
+    pub cost: u32,
+    pub rounds: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams::new(Kdf::default(), KdfPreset::default())
+    }
+}
+
+impl Kdf {
+    /// Stable identifier stored in the stego container header
+    pub fn id(self) -> u8 {
+        match self {
+            Kdf::None => 0,
+            Kdf::Sha256 => 1,
+            Kdf::Argon2id => 2,
+            Kdf::Scrypt => 3,
+            Kdf::Pbkdf2 => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Kdf::None),
+            1 => Some(Kdf::Sha256),
+            2 => Some(Kdf::Argon2id),
+            3 => Some(Kdf::Scrypt),
+            4 => Some(Kdf::Pbkdf2),
+            _ => None,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Cost parameters for `kdf` at the given preset
+    pub fn new(kdf: Kdf, preset: KdfPreset) -> Self {
+        let (cost, rounds, parallelism) = match (kdf, preset) {
+            (Kdf::Argon2id, KdfPreset::Interactive) => (19 * 1024, 2, 1),
+            (Kdf::Argon2id, KdfPreset::Moderate) => (64 * 1024, 3, 1),
+            (Kdf::Argon2id, KdfPreset::Sensitive) => (256 * 1024, 4, 1),
+            (Kdf::Scrypt, KdfPreset::Interactive) => (15, 8, 1),
+            (Kdf::Scrypt, KdfPreset::Moderate) => (17, 8, 1),
+            (Kdf::Scrypt, KdfPreset::Sensitive) => (19, 8, 1),
+            (Kdf::Pbkdf2, KdfPreset::Interactive) => (100_000, 0, 0),
+            (Kdf::Pbkdf2, KdfPreset::Moderate) => (600_000, 0, 0),
+            (Kdf::Pbkdf2, KdfPreset::Sensitive) => (2_000_000, 0, 0),
+            (Kdf::None | Kdf::Sha256, _) => (0, 0, 0),
+        };
+        KdfParams { kdf, cost, rounds, parallelism }
+    }
+
+    /// A single PBKDF2 iteration: salted, but with no key stretching at all.
+    /// For tests and benchmarks, where the KDF's cost would drown out what they measure.
+    pub fn fastest() -> Self {
+        KdfParams { kdf: Kdf::Pbkdf2, cost: 1, rounds: 0, parallelism: 0 }
+    }
+
+    /// Derive a 256-bit key from `key` and `salt`
+    pub fn derive(&self, key: &str, salt: &[u8]) -> Result<[u8; 32], Error> {
+        let mut out = [0u8; 32];
+        match self.kdf {
+            Kdf::None | Kdf::Sha256 => {
+                return Err(Error::InvalidArgument(format!("{:?} is not a salted KDF", self.kdf)));
+            }
+            Kdf::Argon2id => {
+                let params = argon2::Params::new(self.cost, self.rounds, self.parallelism, Some(out.len()))
+                    .map_err(|e| Error::InvalidArgument(format!("Invalid Argon2id parameters: {e}")))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(key.as_bytes(), salt, &mut out)
+                    .map_err(|e| Error::InvalidArgument(format!("Argon2id key derivation failed: {e}")))?;
+            }
+            Kdf::Scrypt => {
+                let log_n = u8::try_from(self.cost)
+                    .map_err(|_| Error::InvalidArgument("Invalid scrypt parameters: log2(N) too large".into()))?;
+                let params = scrypt::Params::new(log_n, self.rounds, self.parallelism, out.len())
+                    .map_err(|e| Error::InvalidArgument(format!("Invalid scrypt parameters: {e}")))?;
+                scrypt::scrypt(key.as_bytes(), salt, &params, &mut out)
+                    .map_err(|e| Error::InvalidArgument(format!("scrypt key derivation failed: {e}")))?;
+            }
+            Kdf::Pbkdf2 => {
+                if self.cost == 0 {
+                    return Err(Error::InvalidArgument("Invalid PBKDF2 parameters: zero iterations".into()));
+                }
+                pbkdf2::pbkdf2_hmac::<Sha256>(key.as_bytes(), salt, self.cost, &mut out);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Whether the costs stay within what extraction is willing to spend
+    pub fn within_limits(&self) -> bool {
+        match self.kdf {
+            Kdf::None | Kdf::Sha256 => true,
+            Kdf::Argon2id => {
+                self.cost <= ARGON2_MAX_MEMORY_KIB
+                    && self.rounds <= ARGON2_MAX_PASSES
+                    && self.parallelism <= MAX_PARALLELISM
+            }
+            Kdf::Scrypt => {
+                // scrypt needs 128 * r * N bytes
+                self.cost < 64
+                    && (128 * u64::from(self.rounds)).checked_mul(1 << self.cost).is_some_and(|m| m <= SCRYPT_MAX_MEMORY)
+                    && self.parallelism <= MAX_PARALLELISM
+            }
+            Kdf::Pbkdf2 => self.cost <= PBKDF2_MAX_ITERATIONS,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; PARAMS_LEN] {
+        let mut out = [0u8; PARAMS_LEN];
+        out[0] = self.kdf.id();
+        out[1..5].copy_from_slice(&self.cost.to_be_bytes());
+        out[5..9].copy_from_slice(&self.rounds.to_be_bytes());
+        out[9..13].copy_from_slice(&self.parallelism.to_be_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < PARAMS_LEN {
+            return Err(Error::Decryption("Data too short to contain KDF parameters".into()));
+        }
+        let kdf = Kdf::from_id(bytes[0])
+            .ok_or_else(|| Error::UnsupportedFormat(format!("Unknown KDF id {}", bytes[0])))?;
+        let params = KdfParams {
+            kdf,
+            cost: u32::from_be_bytes(bytes[1..5].try_into().unwrap()),
+            rounds: u32::from_be_bytes(bytes[5..9].try_into().unwrap()),
+            parallelism: u32::from_be_bytes(bytes[9..13].try_into().unwrap()),
+        };
+        if !params.within_limits() {
+            return Err(Error::UnsupportedFormat(format!("{kdf:?} parameters exceed the supported maximum")));
+        }
+        Ok(params)
+    }
+}
+
+/// Derive a cipher key with `params` under a fresh salt.
+/// Returns the key and the `params | salt` prefix to store in front of the ciphertext.
+/// Only salted KDFs are accepted.
+pub fn seal_key(key: &str, params: &KdfParams) -> Result<([u8; 32], Vec<u8>), Error> {
+    if !params.within_limits() {
+        return Err(Error::InvalidArgument(format!("{:?} parameters exceed the supported maximum", params.kdf)));
+    }
+    let salt: [u8; SALT_LEN] = rand::rng().random();
+    let derived = params.derive(key, &salt)?;
+
+    let mut prefix = params.to_bytes().to_vec();
+    prefix.extend_from_slice(&salt);
+    Ok((derived, prefix))
+}
+
+/// Inverse of [`seal_key`]: read the prefix off `data` and re-derive the key.
+/// Returns the key and the remaining data. A prefix naming an unsalted KDF is refused.
+pub fn open_key<'a>(key: &str, data: &'a [u8]) -> Result<([u8; 32], &'a [u8]), Error> {
+    let params = KdfParams::from_bytes(data)?;
+    if matches!(params.kdf, Kdf::None | Kdf::Sha256) {
+        return Err(Error::UnsupportedFormat("Ciphertext names an unsalted KDF".into()));
+    }
+    if data.len() < PARAMS_LEN + SALT_LEN {
+        return Err(Error::Decryption("Data too short to contain KDF salt".into()));
+    }
+    let (salt, rest) = data[PARAMS_LEN..].split_at(SALT_LEN);
+    Ok((params.derive(key, salt)?, rest))
+}
+
+/// Fixed salt for [`order_seed`]; the order must be derivable from the key alone,
+/// and a salt of its own keeps it unrelated to any encryption key
+const ORDER_SALT: &[u8; SALT_LEN] = b"cryimg-order-v1\0";
+
+/// Derive the seed for the embedding permutation from the passphrase with
+/// Argon2id at the moderate preset, so one secret hides both location and content
+pub fn order_seed(key: &str) -> Result<[u8; 32], Error> {
+    if key.is_empty() {
+        return Err(Error::BadKey("Key-derived order requires a non-empty key".into()));
+    }
+    KdfParams::new(Kdf::Argon2id, KdfPreset::Moderate).derive(key, ORDER_SALT)
+}
+
+/// Derive a 256-bit key from the string using SHA-256
+pub fn sha256_key(key: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    hasher.finalize().into()
+}
+
+/// Short fingerprint of the key, stored in the header so that a wrong key
+/// can be reported as such instead of producing garbage.
+/// Only used for ciphers without a KDF; salted KDFs rely on decryption failing instead,
+/// since an unsalted fingerprint would let an attacker skip the KDF cost.
+pub fn key_check(key: &str) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(b"cryimg-key-check");
+    hasher.update(key.as_bytes());
+    let digest = hasher.finalize();
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kdf_id_roundtrip() {
+        for kdf in [Kdf::None, Kdf::Sha256, Kdf::Argon2id, Kdf::Scrypt, Kdf::Pbkdf2] {
+            assert_eq!(Kdf::from_id(kdf.id()), Some(kdf));
+        }
+        assert_eq!(Kdf::from_id(0xFF), None);
+    }
+
+    #[test]
+    fn kdf_key_check_differs_per_key() {
+        assert_eq!(key_check("secret"), key_check("secret"));
+        assert_ne!(key_check("secret"), key_check("Secret"));
+    }
+
+    #[test]
+    fn kdf_params_roundtrip() {
+        let params = KdfParams::new(Kdf::Scrypt, KdfPreset::Sensitive);
+        assert_eq!(KdfParams::from_bytes(&params.to_bytes()).unwrap(), params);
+    }
+
+    #[test]
+    fn kdf_salted_derivation() {
+        for kdf in [Kdf::Argon2id, Kdf::Scrypt, Kdf::Pbkdf2] {
+            let params = KdfParams::new(kdf, KdfPreset::Interactive);
+            let (derived, prefix) = seal_key("passphrase", &params).unwrap();
+            let (reopened, rest) = open_key("passphrase", &prefix).unwrap();
+            assert_eq!(derived, reopened, "{kdf:?}");
+            assert!(rest.is_empty());
+
+            let (other, _) = open_key("Passphrase", &prefix).unwrap();
+            assert_ne!(derived, other, "{kdf:?}");
+        }
+    }
+
+    #[test]
+    fn kdf_order_seed_is_domain_separated() {
+        let seed = order_seed("passphrase").unwrap();
+        assert_eq!(seed, order_seed("passphrase").unwrap());
+        assert_ne!(seed, order_seed("Passphrase").unwrap());
+
+        let params = KdfParams::new(Kdf::Argon2id, KdfPreset::Moderate);
+        let (key, _) = seal_key("passphrase", &params).unwrap();
+        assert_ne!(seed, key);
+        assert!(matches!(order_seed(""), Err(Error::BadKey(_))));
+    }
+
+    #[test]
+    fn kdf_rejects_oversized_stored_params() {
+        let huge = [
+            KdfParams { kdf: Kdf::Argon2id, cost: u32::MAX, rounds: 3, parallelism: 1 },
+            KdfParams { kdf: Kdf::Argon2id, cost: 64 * 1024, rounds: u32::MAX, parallelism: 1 },
+            KdfParams { kdf: Kdf::Argon2id, cost: 64 * 1024, rounds: 3, parallelism: 1000 },
+            KdfParams { kdf: Kdf::Scrypt, cost: 40, rounds: 8, parallelism: 1 },
+            KdfParams { kdf: Kdf::Scrypt, cost: 17, rounds: 1 << 20, parallelism: 1 },
+            KdfParams { kdf: Kdf::Scrypt, cost: 40, rounds: 1 << 31, parallelism: 1 },
+            KdfParams { kdf: Kdf::Pbkdf2, cost: u32::MAX, rounds: 0, parallelism: 0 },
+        ];
+        for params in huge {
+            let mut data = params.to_bytes().to_vec();
+            data.extend_from_slice(&[0; SALT_LEN + 32]);
+            assert!(matches!(open_key("passphrase", &data), Err(Error::UnsupportedFormat(_))), "{params:?}");
+            assert!(matches!(seal_key("passphrase", &params), Err(Error::InvalidArgument(_))), "{params:?}");
+        }
+        for kdf in [Kdf::Argon2id, Kdf::Scrypt, Kdf::Pbkdf2] {
+            assert!(KdfParams::new(kdf, KdfPreset::Sensitive).within_limits(), "{kdf:?}");
+        }
+    }
+
+    #[test]
+    fn kdf_rejects_unsalted() {
+        for kdf in [Kdf::None, Kdf::Sha256] {
+            let params = KdfParams::new(kdf, KdfPreset::Interactive);
+            assert!(matches!(seal_key("passphrase", &params), Err(Error::InvalidArgument(_))));
+
+            let mut data = params.to_bytes().to_vec();
+            data.extend_from_slice(&[7; SALT_LEN]);
+            assert!(matches!(open_key("passphrase", &data), Err(Error::UnsupportedFormat(_))));
+        }
+    }
+
+    #[test]
+    fn kdf_fresh_salt_per_call() {
+        let params = KdfParams::new(Kdf::Pbkdf2, KdfPreset::Interactive);
+        let (first, _) = seal_key("passphrase", &params).unwrap();
+        let (second, _) = seal_key("passphrase", &params).unwrap();
+        assert_ne!(first, second);
+    }
+}
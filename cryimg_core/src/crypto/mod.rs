@@ -1,10 +1,16 @@
 use std::str::FromStr;
 
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::Error;
+
 pub mod encrypt;
 pub mod decrypt;
+pub mod kdf;
 
 use encrypt::*;
 use decrypt::*;
+use kdf::{Kdf, KdfParams, PARAMS_LEN, SALT_LEN};
 
 
 /// Supported encryption algorithms
@@ -15,10 +21,15 @@ pub enum Algorithm {
     Caesar,
     Rot13,
     Aes,
+    /// AES-256-GCM (authenticated)
+    AesGcm,
+    /// ChaCha20-Poly1305 (authenticated)
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
 }
 
 impl FromStr for Algorithm {
-    type Err = String;
+    type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
@@ -27,41 +38,174 @@ impl FromStr for Algorithm {
             "caesar" => Ok(Algorithm::Caesar),
             "rot13" => Ok(Algorithm::Rot13),
             "aes" => Ok(Algorithm::Aes),
-            other => Err(format!("Unsupported algorithm: {}", other)),
+            "aes-gcm" => Ok(Algorithm::AesGcm),
+            "chacha20-poly1305" => Ok(Algorithm::ChaCha20Poly1305),
+            other => Err(Error::InvalidArgument(format!("Unsupported algorithm: {}", other))),
+        }
+    }
+}
+
+impl Algorithm {
+    /// Stable identifier stored in the stego container header
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::None => 0,
+            Algorithm::Xor => 1,
+            Algorithm::Caesar => 2,
+            Algorithm::Rot13 => 3,
+            Algorithm::Aes => 4,
+            Algorithm::AesGcm => 5,
+            Algorithm::ChaCha20Poly1305 => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Algorithm::None),
+            1 => Some(Algorithm::Xor),
+            2 => Some(Algorithm::Caesar),
+            3 => Some(Algorithm::Rot13),
+            4 => Some(Algorithm::Aes),
+            5 => Some(Algorithm::AesGcm),
+            6 => Some(Algorithm::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    /// Whether the algorithm needs a user-supplied key
+    pub fn requires_key(self) -> bool {
+        !matches!(self, Algorithm::None | Algorithm::Rot13)
+    }
+
+    /// Whether the key is stretched with a salted KDF before use
+    pub fn uses_kdf(self) -> bool {
+        matches!(self, Algorithm::Aes | Algorithm::AesGcm | Algorithm::ChaCha20Poly1305)
+    }
+
+    /// Whether earlier releases base64-encoded this algorithm's output before
+    /// embedding it; see [`dearmor`]
+    pub fn legacy_armored(self) -> bool {
+        matches!(self, Algorithm::Xor | Algorithm::Aes | Algorithm::AesGcm | Algorithm::ChaCha20Poly1305)
+    }
+
+    /// Exact length of the output of [`encrypt_message`] for `plain_len` bytes of
+    /// input, so capacity can be planned without deriving a key
+    pub fn ciphertext_len(self, plain_len: usize) -> usize {
+        let kdf_prefix = PARAMS_LEN + SALT_LEN;
+        match self {
+            Algorithm::None | Algorithm::Xor | Algorithm::Caesar | Algorithm::Rot13 => plain_len,
+            // 16-byte IV, PKCS#7 always adds at least one byte of padding
+            Algorithm::Aes => kdf_prefix + 16 + (plain_len / 16 + 1) * 16,
+            // 12-byte nonce and 16-byte tag
+            Algorithm::AesGcm | Algorithm::ChaCha20Poly1305 => kdf_prefix + 12 + plain_len + 16,
         }
     }
 }
 
 /// Encrypt a message using the given algorithm and key
-pub fn encrypt_message(msg: &str, key: &str, algo: Algorithm) -> Result<String, String> {
+pub fn encrypt_message(msg: &[u8], key: &str, algo: Algorithm) -> Result<Vec<u8>, Error> {
+    encrypt_message_with_kdf(msg, key, algo, &KdfParams::default())
+}
+
+/// Encrypt a message, stretching the key with `kdf` for algorithms that use one
+pub fn encrypt_message_with_kdf(
+    msg: &[u8],
+    key: &str,
+    algo: Algorithm,
+    kdf: &KdfParams,
+) -> Result<Vec<u8>, Error> {
     match algo {
-        Algorithm::None => Ok(msg.to_string()),
+        Algorithm::None => Ok(msg.to_vec()),
         Algorithm::Xor => xor_encrypt(msg, key),
         Algorithm::Caesar => caesar_encrypt(msg, key),
         Algorithm::Rot13 => rot13_encrypt(msg),
-        Algorithm::Aes => aes_encrypt(msg, key),
+        Algorithm::Aes => aes_encrypt(msg, key, kdf),
+        Algorithm::AesGcm => aes_gcm_encrypt(msg, key, kdf),
+        Algorithm::ChaCha20Poly1305 => chacha20_poly1305_encrypt(msg, key, kdf),
     }
 }
 
 /// Decrypt a message using the given algorithm and key
-pub fn decrypt_message(cipher: &str, key: &str, algo: Algorithm) -> Result<String, String> {
+pub fn decrypt_message(cipher: &[u8], key: &str, algo: Algorithm) -> Result<Vec<u8>, Error> {
     match algo {
-        Algorithm::None => Ok(cipher.to_string()),
+        Algorithm::None => Ok(cipher.to_vec()),
         Algorithm::Xor => xor_decrypt(cipher, key),
         Algorithm::Caesar => caesar_decrypt(cipher, key),
         Algorithm::Rot13 => rot13_decrypt(cipher),
         Algorithm::Aes => aes_decrypt(cipher, key),
+        Algorithm::AesGcm => aes_gcm_decrypt(cipher, key),
+        Algorithm::ChaCha20Poly1305 => chacha20_poly1305_decrypt(cipher, key),
+    }
+}
+
+/// Decrypt a message whose key was derived with `kdf`; [`Kdf::Sha256`]
+/// selects the unsalted format written before salted KDFs existed
+pub fn decrypt_message_with_kdf(
+    cipher: &[u8],
+    key: &str,
+    algo: Algorithm,
+    kdf: Kdf,
+) -> Result<Vec<u8>, Error> {
+    match (algo, kdf) {
+        (Algorithm::Aes, Kdf::Sha256) => legacy_aes_decrypt(cipher, key),
+        _ => decrypt_message(cipher, key, algo),
     }
 }
 
+/// Base64 text armor for ciphertext that has to survive copy and paste.
+/// Never applied to embedded payloads, which are stored as raw bytes.
+pub fn armor(cipher: &[u8]) -> String {
+    general_purpose::STANDARD.encode(cipher)
+}
+
+/// Inverse of [`armor`]; surrounding whitespace is ignored
+pub fn dearmor(text: &[u8]) -> Result<Vec<u8>, Error> {
+    general_purpose::STANDARD
+        .decode(text.trim_ascii())
+        .map_err(|e| Error::Decryption(format!("Base64 decode error: {e}")))
+}
+
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn crypto_ciphertext_len_is_exact() {
+        // Lengths do not depend on the KDF
+        let kdf = KdfParams::fastest();
+        for algo in [
+            Algorithm::None,
+            Algorithm::Xor,
+            Algorithm::Caesar,
+            Algorithm::Rot13,
+            Algorithm::Aes,
+            Algorithm::AesGcm,
+            Algorithm::ChaCha20Poly1305,
+        ] {
+            for len in [0, 1, 15, 16, 17, 100] {
+                let encrypted = encrypt_message_with_kdf(&vec![b'a'; len], "7", algo, &kdf).unwrap();
+                assert_eq!(algo.ciphertext_len(len), encrypted.len(), "{algo:?} {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn crypto_armor_roundtrip() {
+        let cipher: Vec<u8> = (0..=255).collect();
+        let text = armor(&cipher);
+        assert!(text.is_ascii());
+        assert_eq!(dearmor(format!("{text}\n").as_bytes()).unwrap(), cipher);
+        assert!(matches!(dearmor(b"not base64!"), Err(Error::Decryption(_))));
+
+        // Ciphertext is raw bytes, only as long as the message for XOR
+        let encrypted = encrypt_message(b"Hello, World!", "key", Algorithm::Xor).unwrap();
+        assert_eq!(encrypted.len(), 13);
+    }
+
     #[test]
     fn crypto_encrypt_decrypt_aes() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "mysecretkey";
         let algo = Algorithm::Aes;
 
@@ -71,8 +215,37 @@ mod tests {
         assert_eq!(decrypted, msg);
     }
     #[test]
+    fn crypto_encrypt_decrypt_aead() {
+        let msg = b"Hello, World!";
+        let key = "mysecretkey";
+
+        for algo in [Algorithm::AesGcm, Algorithm::ChaCha20Poly1305] {
+            let encrypted = encrypt_message(msg, key, algo).unwrap();
+            let decrypted = decrypt_message(&encrypted, key, algo).unwrap();
+            assert_eq!(decrypted, msg);
+            assert!(decrypt_message(&encrypted, "otherkey", algo).is_err());
+        }
+    }
+    #[test]
+    fn crypto_encrypt_with_kdf_choice() {
+        let msg = b"Hello, World!";
+        let key = "mysecretkey";
+        let params = KdfParams::new(Kdf::Pbkdf2, kdf::KdfPreset::Interactive);
+
+        let encrypted = encrypt_message_with_kdf(msg, key, Algorithm::Aes, &params).unwrap();
+        let decrypted = decrypt_message_with_kdf(&encrypted, key, Algorithm::Aes, Kdf::Pbkdf2).unwrap();
+        assert_eq!(decrypted, msg);
+    }
+    #[test]
+    fn crypto_algorithm_ids() {
+        for name in ["none", "xor", "caesar", "rot13", "aes", "aes-gcm", "chacha20-poly1305"] {
+            let algo = Algorithm::from_str(name).unwrap();
+            assert_eq!(Algorithm::from_id(algo.id()), Some(algo));
+        }
+    }
+    #[test]
     fn crypto_encrypt_decrypt_xor() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "key";
         let algo = Algorithm::Xor;
 
@@ -83,7 +256,7 @@ mod tests {
     }
     #[test]
     fn crypto_encrypt_decrypt_caesar() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "3"; // Shift by 3
         let algo = Algorithm::Caesar;
 
@@ -94,7 +267,7 @@ mod tests {
     }
     #[test]
     fn crypto_encrypt_decrypt_rot13() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let algo = Algorithm::Rot13;
 
         let encrypted = encrypt_message(msg, "", algo).unwrap();
@@ -104,7 +277,7 @@ mod tests {
     }
     #[test]
     fn crypto_encrypt_decrypt_none() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let algo = Algorithm::None;
 
         let encrypted = encrypt_message(msg, "", algo).unwrap();
@@ -113,7 +286,7 @@ mod tests {
         assert_eq!(decrypted, msg);
     }
     #[test]
-    fn crypto_invalid_algorithm() -> Result<(), String> {
+    fn crypto_invalid_algorithm() -> Result<(), Error> {
         match Algorithm::from_str("invalid") {
             Ok(algorithm) => {
                 panic!("Expected error for invalid algorithm, but got: {:?}", algorithm);
@@ -128,17 +301,17 @@ mod tests {
 
     #[test]
     fn crypto_encrypt_with_empty_key() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let algo = Algorithm::Xor;
 
         let encrypted = encrypt_message(msg, "", algo);
         assert!(encrypted.is_err());
-        assert_eq!(encrypted.unwrap_err(), "XOR encryption requires a non-empty key");
+        assert_eq!(encrypted.unwrap_err(), Error::BadKey("XOR encryption requires a non-empty key".into()));
     }
 
     #[test]
     fn crypto_decrypt_with_empty_key() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "secret";
         let algo = Algorithm::Xor;
 
@@ -148,21 +321,21 @@ mod tests {
         // Now attempt to decrypt with an empty key
         let decrypted = decrypt_message(&encrypted, "", algo);
         assert!(decrypted.is_err());
-        assert_eq!(decrypted.unwrap_err(), "XOR decryption requires a non-empty key");
+        assert_eq!(decrypted.unwrap_err(), Error::BadKey("XOR decryption requires a non-empty key".into()));
     }
 
     #[test]
     fn crypto_caesar_encrypt_invalid_key() {
-        let msg = "Hello, World!";
+        let msg = b"Hello, World!";
         let key = "invalid"; // Non-numeric key
         let algo = Algorithm::Caesar;
 
         let encrypted = encrypt_message(msg, key, algo);
         assert!(encrypted.is_err());
-        assert_eq!(encrypted.unwrap_err(), "Key must be a number for Caesar cipher");
+        assert_eq!(encrypted.unwrap_err(), Error::BadKey("Key must be a number for Caesar cipher".into()));
 
         let decrypted = decrypt_message(msg, key, algo);
         assert!(decrypted.is_err());
-        assert_eq!(decrypted.unwrap_err(), "Key must be a number for Caesar cipher");
+        assert_eq!(decrypted.unwrap_err(), Error::BadKey("Key must be a number for Caesar cipher".into()));
     }
 }
\ No newline at end of file
@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// Errors returned by every fallible function in `cryimg_core`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Reading, decoding, encoding or writing an image or file failed
+    Io(String),
+    /// The payload does not fit in the cover image
+    CapacityExceeded { needed: usize, available: usize },
+    /// PRNG ordering was requested without a seed
+    MissingSeed,
+    /// The key is missing, malformed, or not the one used to embed
+    BadKey(String),
+    /// Decryption failed (bad encoding, padding, truncated data)
+    Decryption(String),
+    /// An AEAD tag did not verify: wrong key or tampered ciphertext
+    Authentication,
+    /// The image carries no container header
+    NoPayload,
+    /// A header was found but its contents are invalid
+    CorruptHeader(String),
+    /// The payload does not match its checksum or metadata
+    CorruptPayload(String),
+    /// A container version, algorithm or image format this build cannot handle
+    UnsupportedFormat(String),
+    /// A parameter is out of range or otherwise unusable
+    InvalidArgument(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(msg) => write!(f, "{msg}"),
+            Error::CapacityExceeded { needed, available } => write!(
+                f,
+                "Message too long to fit in image: needs {needed} bytes, capacity is {available} bytes"
+            ),
+            Error::MissingSeed => write!(f, "PRNG selected but no seed provided"),
+            Error::BadKey(msg) => write!(f, "{msg}"),
+            Error::Decryption(msg) => write!(f, "{msg}"),
+            Error::Authentication => write!(f, "Authentication failed: wrong key or tampered ciphertext"),
+            Error::NoPayload => write!(f, "No payload found in image"),
+            Error::CorruptHeader(msg) => write!(f, "Corrupt header: {msg}"),
+            Error::CorruptPayload(msg) => write!(f, "Corrupt payload: {msg}"),
+            Error::UnsupportedFormat(msg) => write!(f, "{msg}"),
+            Error::InvalidArgument(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,3 +1,6 @@
+pub mod error;
 pub mod utils;
 pub mod crypto;
-pub mod stegano;
\ No newline at end of file
+pub mod stegano;
+
+pub use error::Error;
\ No newline at end of file
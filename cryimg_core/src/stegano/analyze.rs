@@ -1,53 +1,111 @@
 use image::{DynamicImage, GenericImageView};
-use crate::crypto::{encrypt_message, Algorithm};
+use crate::Error;
+use crate::crypto::Algorithm;
+use super::channels::Channels;
+use super::compression::Compression;
+use super::ecc::Ecc;
+use super::header::{HEADER_LEN, MAX_DEPTH};
+use super::layout::{container_len, Layout};
 
 pub struct AnalysisReport {
     pub image_dimensions: (u32, u32),
+    /// Channels the payload would go into, with `Auto` resolved
+    pub channels: Channels,
+    /// Header plus payload bytes that fit at one bit per channel
     pub max_capacity_bytes: usize,
     pub input_text_len: usize,
+    /// Codec the message would be compressed with, with `Auto` resolved
+    pub compression: Compression,
+    /// Size after compression, when a codec is used
+    pub compressed_len: Option<usize>,
     pub encrypted_len: Option<usize>,
     pub prefix_overhead_bytes: usize,
+    pub ecc: Ecc,
+    /// Reed-Solomon parity for the header and payload
+    pub ecc_overhead_bytes: usize,
     pub total_payload_bytes: usize,
     pub algorithm: Algorithm,
     pub can_fit: bool,
+    /// Capacity and expected distortion at each embedding depth, 1 to [`MAX_DEPTH`]
+    pub depths: Vec<DepthReport>,
 }
 
+/// What embedding this payload at a given number of bits per channel costs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthReport {
+    pub bits_per_channel: u8,
+    /// Header plus payload bytes that fit at this depth
+    pub max_capacity_bytes: usize,
+    pub can_fit: bool,
+    /// Expected mean squared error over all channels when embedding this payload
+    /// with LSB replacement (random payload bits)
+    pub expected_mse: f64,
+    /// Peak signal-to-noise ratio for `expected_mse`, in dB; infinite when nothing changes
+    pub expected_psnr: f64,
+}
+
+/// Size up embedding `message` (already encoded, see [`super::message::Message::encode`])
+/// compressed with `compression`, encrypted with `algorithm` and protected with `ecc`
+/// into `img`. Uses the same [`Layout`] as embedding, so `can_fit` is exactly whether
+/// embedding would succeed.
 pub fn analyze_capacity(
     img: &DynamicImage,
-    message: &str,
+    message: &[u8],
     algorithm: Algorithm,
-    key: &str,
-) -> Result<AnalysisReport, String> {
-    let (width, height) = img.dimensions();
-    let total_pixels = width * height;
-    let channels = img.color().channel_count() as u32;
-    let max_capacity_bits = total_pixels * channels; // 1 bit per channel
-    let max_capacity_bytes = (max_capacity_bits / 8) as usize;
-
-    let prefix_overhead_bytes = 4;
+    channels: Channels,
+    compression: Compression,
+    ecc: Ecc,
+) -> Result<AnalysisReport, Error> {
+    let layout = Layout::new(img, channels, 1, ecc);
+    let message_len = message.len();
+    let (compression, compressed) = compression.apply(message)?;
+    let compressed_len = (compression != Compression::None).then_some(compressed.len());
+    let plain_len = compressed.len();
     let encrypted_len = match algorithm {
         Algorithm::None => None,
-        _ => {
-            let encrypted = encrypt_message(message, key, algorithm)?;
-            Some(encrypted.len())
-        }
+        _ => Some(algorithm.ciphertext_len(plain_len)),
     };
+    let payload_len = container_len(plain_len, algorithm, ecc);
 
-    let message_len = message.len();
-
-    let payload_len = match encrypted_len {
-        Some(enc_len) => enc_len + prefix_overhead_bytes,
-        None => message_len + prefix_overhead_bytes,
-    };
+    let depths = (1..=MAX_DEPTH)
+        .map(|depth| depth_report(&Layout { depth, ..layout }, payload_len))
+        .collect();
 
     Ok(AnalysisReport {
-        image_dimensions: (width, height),
-        max_capacity_bytes,
+        image_dimensions: img.dimensions(),
+        channels: layout.channels,
+        max_capacity_bytes: layout.capacity(),
         input_text_len: message_len,
+        compression,
+        compressed_len,
         encrypted_len,
-        prefix_overhead_bytes,
+        prefix_overhead_bytes: HEADER_LEN,
+        ecc,
+        ecc_overhead_bytes: payload_len - HEADER_LEN - algorithm.ciphertext_len(plain_len),
         total_payload_bytes: payload_len,
         algorithm,
-        can_fit: payload_len <= max_capacity_bytes,
+        can_fit: layout.fits(payload_len - ecc.header_len()),
+        depths,
     })
 }
+
+/// The header always takes one bit per channel; the payload after it takes `depth`
+fn depth_report(layout: &Layout, payload_len: usize) -> DepthReport {
+    let depth = layout.depth;
+    let channel_count = layout.slots();
+    let payload_len = payload_len - layout.ecc.header_len();
+    let payload_channels = layout.payload_slots(payload_len);
+
+    // A replaced k-bit field differs from a uniform random one by e, E[e²] = (4^k - 1) / 6
+    let squared_error = layout.header_slots() as f64 * 0.5
+        + payload_channels as f64 * ((1u32 << (2 * depth)) - 1) as f64 / 6.0;
+    let expected_mse = if channel_count == 0 { 0.0 } else { squared_error / channel_count as f64 };
+
+    DepthReport {
+        bits_per_channel: depth,
+        max_capacity_bytes: layout.capacity(),
+        can_fit: layout.fits(payload_len),
+        expected_mse,
+        expected_psnr: 10.0 * (255.0f64 * 255.0 / expected_mse).log10(),
+    }
+}
@@ -0,0 +1,175 @@
+use image::{ColorType, DynamicImage, GrayAlphaImage, GrayImage, Luma, LumaA, RgbaImage};
+
+/// Which channels of each pixel carry payload bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Channels {
+    /// RGB, plus alpha only if the cover already has transparent pixels
+    #[default]
+    Auto,
+    /// Red, green and blue; alpha is left untouched
+    Rgb,
+    /// All four channels, including alpha (the format before channel selection)
+    Rgba,
+    Red,
+    Green,
+    Blue,
+    /// The gray level of a grayscale cover; chosen by [`Channels::resolve`]
+    #[value(skip)]
+    Luma,
+    /// Gray level and alpha of a grayscale cover; chosen by [`Channels::resolve`]
+    #[value(skip)]
+    LumaAlpha,
+}
+
+impl Channels {
+    /// Every concrete selection, in the order extraction tries them
+    pub const CONCRETE: [Channels; 7] = [
+        Channels::Rgb,
+        Channels::Rgba,
+        Channels::Red,
+        Channels::Green,
+        Channels::Blue,
+        Channels::Luma,
+        Channels::LumaAlpha,
+    ];
+
+    /// Pick the concrete selection for `img`. Writing into an opaque alpha
+    /// channel would make the noise plainly visible, so `Auto` only does so
+    /// when the cover already has transparency.
+    ///
+    /// A grayscale cover has a single gray channel, so every selection maps to
+    /// [`Channels::Luma`], or [`Channels::LumaAlpha`] where it would include alpha.
+    pub fn resolve(self, img: &DynamicImage) -> Channels {
+        if !img.color().has_color() {
+            return match self {
+                Channels::Auto if has_transparency(img) => Channels::LumaAlpha,
+                Channels::Rgba | Channels::LumaAlpha => Channels::LumaAlpha,
+                _ => Channels::Luma,
+            };
+        }
+        match self {
+            Channels::Auto if has_transparency(img) => Channels::Rgba,
+            Channels::Auto => Channels::Rgb,
+            concrete => concrete,
+        }
+    }
+
+    /// Indices into an RGBA pixel; an unresolved `Auto` behaves like `Rgb`
+    pub fn indices(self) -> &'static [usize] {
+        match self {
+            Channels::Auto | Channels::Rgb => &[0, 1, 2],
+            Channels::Rgba => &[0, 1, 2, 3],
+            Channels::Red | Channels::Luma => &[0],
+            Channels::Green => &[1],
+            Channels::Blue => &[2],
+            Channels::LumaAlpha => &[0, 3],
+        }
+    }
+
+    pub fn count(self) -> usize {
+        self.indices().len()
+    }
+
+    /// Stable identifier stored in the header flags; zero is `Rgba` so images
+    /// embedded before channel selection existed read back unchanged
+    pub fn id(self) -> u8 {
+        match self {
+            Channels::Rgba => 0,
+            Channels::Auto | Channels::Rgb => 1,
+            Channels::Red => 2,
+            Channels::Green => 3,
+            Channels::Blue => 4,
+            Channels::Luma => 5,
+            Channels::LumaAlpha => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Channels::Rgba),
+            1 => Some(Channels::Rgb),
+            2 => Some(Channels::Red),
+            3 => Some(Channels::Green),
+            4 => Some(Channels::Blue),
+            5 => Some(Channels::Luma),
+            6 => Some(Channels::LumaAlpha),
+            _ => None,
+        }
+    }
+}
+
+fn has_transparency(img: &DynamicImage) -> bool {
+    img.color().has_alpha() && img.to_rgba8().pixels().any(|px| px[3] != u8::MAX)
+}
+
+/// Convert the working RGBA buffer back to the cover's layout: covers without alpha
+/// stay without alpha unless the payload went into it, and grayscale covers stay
+/// grayscale, keeping the gray level from the first channel as [`Channels::Luma`]
+/// wrote it. 16-bit covers are downconverted to 8 bits, since the payload bits
+/// would not survive scaling them back up.
+pub fn restore_color_type(img: RgbaImage, source: ColorType, channels: Channels) -> DynamicImage {
+    let alpha = source.has_alpha() || channels.indices().contains(&3);
+    let (width, height) = img.dimensions();
+    match (source.has_color(), alpha) {
+        (false, false) => DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([img.get_pixel(x, y)[0]])
+        })),
+        (false, true) => DynamicImage::ImageLumaA8(GrayAlphaImage::from_fn(width, height, |x, y| {
+            let px = img.get_pixel(x, y);
+            LumaA([px[0], px[3]])
+        })),
+        (true, false) => DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(img).to_rgb8()),
+        (true, true) => DynamicImage::ImageRgba8(img),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_id_roundtrip() {
+        for channels in Channels::CONCRETE {
+            assert_eq!(Channels::from_id(channels.id()), Some(channels));
+        }
+        assert_eq!(Channels::from_id(7), None);
+    }
+
+    #[test]
+    fn channels_auto_skips_opaque_alpha() {
+        let opaque = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, image::Rgba([1, 2, 3, 255])));
+        assert_eq!(Channels::Auto.resolve(&opaque), Channels::Rgb);
+
+        let mut translucent = RgbaImage::from_pixel(4, 4, image::Rgba([1, 2, 3, 255]));
+        translucent.put_pixel(3, 3, image::Rgba([1, 2, 3, 128]));
+        assert_eq!(Channels::Auto.resolve(&DynamicImage::ImageRgba8(translucent)), Channels::Rgba);
+
+        assert_eq!(Channels::Blue.resolve(&opaque), Channels::Blue);
+    }
+
+    #[test]
+    fn channels_grayscale_use_luma() {
+        let gray = DynamicImage::ImageLuma8(GrayImage::from_pixel(4, 4, Luma([9])));
+        for channels in [Channels::Auto, Channels::Rgb, Channels::Red, Channels::Blue] {
+            assert_eq!(channels.resolve(&gray), Channels::Luma, "{channels:?}");
+        }
+        assert_eq!(Channels::Rgba.resolve(&gray), Channels::LumaAlpha);
+
+        let mut translucent = GrayAlphaImage::from_pixel(4, 4, LumaA([9, 255]));
+        translucent.put_pixel(0, 0, LumaA([9, 0]));
+        assert_eq!(Channels::Auto.resolve(&DynamicImage::ImageLumaA8(translucent)), Channels::LumaAlpha);
+    }
+
+    #[test]
+    fn channels_restore_grayscale() {
+        let rgba = RgbaImage::from_pixel(2, 2, image::Rgba([8, 9, 9, 200]));
+        let luma = restore_color_type(rgba.clone(), ColorType::L8, Channels::Luma);
+        assert_eq!(luma.as_luma8().unwrap().get_pixel(1, 1), &Luma([8]));
+
+        let luma_alpha = restore_color_type(rgba.clone(), ColorType::L16, Channels::LumaAlpha);
+        assert_eq!(luma_alpha.as_luma_alpha8().unwrap().get_pixel(1, 1), &LumaA([8, 200]));
+
+        assert_eq!(restore_color_type(rgba, ColorType::Rgb16, Channels::Rgb).color(), ColorType::Rgb8);
+    }
+}
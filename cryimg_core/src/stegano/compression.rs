@@ -0,0 +1,141 @@
+use std::io::{Read, Write};
+
+use crate::Error;
+
+/// Decompressed payloads larger than this are rejected, so a crafted image
+/// cannot expand into an unbounded amount of memory
+pub const MAX_DECOMPRESSED_LEN: u64 = 1 << 30;
+
+/// How the plaintext is compressed before encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Compression {
+    /// Try every codec and keep the smallest result, or none if nothing shrinks
+    Auto,
+    #[default]
+    None,
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    /// Every concrete codec, in the order `Auto` tries them
+    pub const CODECS: [Compression; 3] = [Compression::Deflate, Compression::Zstd, Compression::Brotli];
+
+    /// Stable identifier stored in the stego container header; an unresolved
+    /// `Auto` is never written
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::None | Compression::Auto => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Deflate),
+            2 => Some(Compression::Zstd),
+            3 => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    /// Compress `data`, resolving `Auto` to the codec that helps most.
+    /// Returns the codec actually used and its output.
+    pub fn apply(self, data: &[u8]) -> Result<(Compression, Vec<u8>), Error> {
+        if self != Compression::Auto {
+            return Ok((self, self.compress(data)?));
+        }
+        let mut best = (Compression::None, data.to_vec());
+        for codec in Compression::CODECS {
+            let compressed = codec.compress(data)?;
+            if compressed.len() < best.1.len() {
+                best = (codec, compressed);
+            }
+        }
+        Ok(best)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let failed = |e: std::io::Error| Error::InvalidArgument(format!("{self:?} compression failed: {e}"));
+        match self {
+            Compression::None | Compression::Auto => Ok(data.to_vec()),
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data).map_err(failed)?;
+                encoder.finish().map_err(failed)
+            }
+            Compression::Zstd => zstd::bulk::compress(data, 19).map_err(failed),
+            Compression::Brotli => {
+                let mut out = Vec::new();
+                let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 9, 22);
+                encoder.write_all(data).map_err(failed)?;
+                drop(encoder);
+                Ok(out)
+            }
+        }
+    }
+
+    /// Inverse of [`Compression::compress`], capped at [`MAX_DECOMPRESSED_LEN`]
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::None | Compression::Auto => return Ok(data.to_vec()),
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            Compression::Zstd => Box::new(
+                zstd::stream::read::Decoder::new(data)
+                    .map_err(|e| Error::CorruptPayload(format!("zstd decompression failed: {e}")))?,
+            ),
+            Compression::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+        };
+
+        let mut out = Vec::new();
+        reader
+            .take(MAX_DECOMPRESSED_LEN + 1)
+            .read_to_end(&mut out)
+            .map_err(|e| Error::CorruptPayload(format!("{self:?} decompression failed: {e}")))?;
+        if out.len() as u64 > MAX_DECOMPRESSED_LEN {
+            return Err(Error::CorruptPayload(format!(
+                "decompressed payload exceeds {MAX_DECOMPRESSED_LEN} bytes"
+            )));
+        }
+        Ok(out)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression_roundtrip() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(40);
+        for codec in [Compression::None, Compression::Deflate, Compression::Zstd, Compression::Brotli] {
+            let compressed = codec.compress(text.as_bytes()).unwrap();
+            assert_eq!(codec.decompress(&compressed).unwrap(), text.as_bytes(), "{codec:?}");
+            assert_eq!(Compression::from_id(codec.id()), Some(codec));
+        }
+    }
+
+    #[test]
+    fn compression_auto_only_when_it_helps() {
+        let text = "to be or not to be, ".repeat(50);
+        let (codec, compressed) = Compression::Auto.apply(text.as_bytes()).unwrap();
+        assert_ne!(codec, Compression::None);
+        assert!(compressed.len() < text.len() / 4);
+
+        // A few bytes only grow once framed
+        assert_eq!(Compression::Auto.apply(b"hi").unwrap(), (Compression::None, b"hi".to_vec()));
+    }
+
+    #[test]
+    fn compression_rejects_garbage() {
+        for codec in Compression::CODECS {
+            let result = codec.decompress(b"definitely not compressed data");
+            assert!(matches!(result, Err(Error::CorruptPayload(_))), "{codec:?}");
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use image::DynamicImage;
+
+use super::extract::{read_header_with_options, ExtractOptions};
+use super::header::Header;
+
+/// Result of looking for hidden data in an image without the key
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    /// Container header, if one was found at the start of the LSB stream
+    pub header: Option<Header>,
+    /// Chi-square statistic of the pairs-of-values test over all channels
+    pub chi_square: f64,
+    /// Probability that the LSBs were overwritten with random data (0.0 – 1.0).
+    /// High values mean the histogram pairs `2k`/`2k+1` are suspiciously even.
+    pub embedding_probability: f64,
+}
+
+impl Detection {
+    pub fn is_suspicious(&self) -> bool {
+        self.header.is_some() || self.embedding_probability > 0.95
+    }
+}
+
+/// Look for a payload: first for a container header in the order `options` describe,
+/// then with the Westfeld–Pfitzmann chi-square attack, which also catches scrambled
+/// or foreign LSB embedding
+pub fn detect(img: &DynamicImage, options: &ExtractOptions) -> Detection {
+    let header = read_header_with_options(img, options).ok();
+    let (chi_square, embedding_probability) = chi_square_attack(img);
+    Detection { header, chi_square, embedding_probability }
+}
+
+/// Pairs-of-values test. Overwriting LSBs with random bits equalizes the counts
+/// of each value pair `2k`/`2k+1`; returns the statistic and the p-value that
+/// the observed counts came from such equalized pairs
+fn chi_square_attack(img: &DynamicImage) -> (f64, f64) {
+    let mut histogram = [0u64; 256];
+    for value in img.to_rgba8().pixels().flat_map(|px| px.0.into_iter().take(3)) {
+        histogram[value as usize] += 1;
+    }
+
+    let mut chi_square = 0.0;
+    let mut dof = 0u32;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // Pairs with too few samples only add noise
+        if expected < 5.0 {
+            continue;
+        }
+        let diff = pair[0] as f64 - expected;
+        chi_square += diff * diff / expected;
+        dof += 1;
+    }
+    if dof < 2 {
+        return (chi_square, 0.0);
+    }
+
+    (chi_square, chi_square_sf(chi_square, (dof - 1) as f64))
+}
+
+/// Upper tail of the chi-square distribution via the Wilson–Hilferty approximation
+fn chi_square_sf(x: f64, k: f64) -> f64 {
+    let scale = 2.0 / (9.0 * k);
+    let z = ((x / k).cbrt() - (1.0 - scale)) / scale.sqrt();
+    0.5 * erfc(z / std::f64::consts::SQRT_2)
+}
+
+/// Complementary error function (Numerical Recipes `erfcc`, |error| < 1.2e-7)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let r = t * poly.exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use crate::crypto::Algorithm;
+    use crate::stegano::embed::{embed_to_image, EmbedOptions, EmbedStrategy};
+    use crate::stegano::message::Message;
+
+    /// Smooth gradient: neighbouring values are unevenly represented, like a photo
+    fn cover() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+            let v = ((x * 3 + y * 2) / 2) as u8 & 0xFE;
+            image::Rgba([v, v.wrapping_add(40), v / 2, 255])
+        }))
+    }
+
+    /// Smooth, skewed histogram: counts fall off steadily, so every pair `2k`/`2k+1` is uneven
+    fn photo_like() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(96, 96, |x, y| {
+            let u = (y * 96 + x) as f64 / (96.0 * 96.0);
+            let v = (64.0 * u * u) as u8;
+            image::Rgba([v, 100 + v / 2, 200 - v, 255])
+        }))
+    }
+
+    #[test]
+    fn detect_clean_image() {
+        let detection = detect(&cover(), &ExtractOptions::default());
+        assert!(detection.header.is_none());
+        assert!(!detection.is_suspicious());
+    }
+
+    #[test]
+    fn detect_container_header() {
+        let options = EmbedOptions { algorithm: Algorithm::Rot13, ..Default::default() };
+        let stego = embed_to_image(&cover(), &Message::new(b"hi".to_vec()), "", &options).unwrap();
+        let detection = detect(&stego, &ExtractOptions::default());
+        assert_eq!(detection.header.unwrap().algorithm, Algorithm::Rot13);
+        // A header in PRNG order is only found in that order
+        let seed = Some(crate::utils::Seed::new("order"));
+        let options = EmbedOptions { use_prng: true, seed, ..options };
+        let stego = embed_to_image(&cover(), &Message::new(b"hi".to_vec()), "", &options).unwrap();
+        assert_eq!(detect(&stego, &ExtractOptions::default()).header, None);
+        assert!(detect(&stego, &ExtractOptions::new(true, seed)).header.is_some());
+    }
+
+    #[test]
+    fn detect_random_lsbs() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let mut img = cover().to_rgba8();
+        for px in img.pixels_mut() {
+            for channel in px.0.iter_mut().take(3) {
+                *channel = (*channel & 0xFE) | rng.random_range(0..2);
+            }
+        }
+        let detection = detect(&DynamicImage::ImageRgba8(img), &ExtractOptions::default());
+        assert!(detection.header.is_none());
+        assert!(detection.is_suspicious(), "{detection:?}");
+    }
+
+    #[test]
+    fn detect_misses_lsb_matching() {
+        // Fill the image with random bytes, in scrambled order so no header is readable
+        let mut rng = ChaCha20Rng::seed_from_u64(2);
+        let message = Message::new((0..3400).map(|_| rng.random::<u8>()).collect::<Vec<_>>());
+        let embed = |strategy| {
+            let options = EmbedOptions {
+                use_prng: true,
+                seed: Some(crate::utils::Seed::new("order")),
+                strategy,
+                ..Default::default()
+            };
+            detect(&embed_to_image(&photo_like(), &message, "key", &options).unwrap(), &ExtractOptions::default())
+        };
+
+        assert!(embed(EmbedStrategy::Replace).embedding_probability > 0.95);
+        assert!(embed(EmbedStrategy::Match).embedding_probability < 0.05);
+    }
+}
@@ -0,0 +1,223 @@
+use reed_solomon::{Decoder, Encoder};
+
+use crate::Error;
+use super::header::HEADER_LEN;
+
+/// Bytes in a Reed-Solomon codeword over GF(2^8)
+const CODEWORD_LEN: usize = 255;
+
+/// Parity written after the header when the payload uses error correction,
+/// enough to repair any 8 of the header's bytes
+pub const HEADER_PARITY: usize = 16;
+
+/// How much Reed-Solomon parity protects the embedded payload. The payload is cut
+/// into codewords of up to 255 bytes whose bytes are interleaved, so damage to a
+/// run of neighbouring channels is spread over many codewords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Ecc {
+    #[default]
+    None,
+    /// 8 parity bytes per codeword, repairs 4 bytes in each (about 3% overhead)
+    Low,
+    /// 16 parity bytes per codeword, repairs 8 bytes in each (about 7% overhead)
+    Medium,
+    /// 32 parity bytes per codeword, repairs 16 bytes in each (about 14% overhead)
+    High,
+    /// 64 parity bytes per codeword, repairs 32 bytes in each (about 34% overhead)
+    Max,
+}
+
+impl Ecc {
+    /// Stable identifier stored in the stego container header
+    pub fn id(self) -> u8 {
+        match self {
+            Ecc::None => 0,
+            Ecc::Low => 1,
+            Ecc::Medium => 2,
+            Ecc::High => 3,
+            Ecc::Max => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Ecc::None),
+            1 => Some(Ecc::Low),
+            2 => Some(Ecc::Medium),
+            3 => Some(Ecc::High),
+            4 => Some(Ecc::Max),
+            _ => None,
+        }
+    }
+
+    /// Parity bytes in each codeword
+    pub fn parity(self) -> usize {
+        match self {
+            Ecc::None => 0,
+            Ecc::Low => 8,
+            Ecc::Medium => 16,
+            Ecc::High => 32,
+            Ecc::Max => 64,
+        }
+    }
+
+    /// Bytes embedded in front of the payload: the header, and its parity if any
+    pub fn header_len(self) -> usize {
+        match self {
+            Ecc::None => HEADER_LEN,
+            _ => HEADER_LEN + HEADER_PARITY,
+        }
+    }
+
+    /// Length of `data_len` bytes once encoded
+    pub fn encoded_len(self, data_len: usize) -> usize {
+        data_len + codewords(data_len, self.parity()) * self.parity()
+    }
+
+    /// Inverse of [`Ecc::encoded_len`]; `None` if no data length encodes to `encoded_len`
+    pub fn decoded_len(self, encoded_len: usize) -> Option<usize> {
+        let parity = self.parity();
+        let data_len = encoded_len.checked_sub(encoded_len.div_ceil(CODEWORD_LEN) * parity)?;
+        (self.encoded_len(data_len) == encoded_len).then_some(data_len)
+    }
+
+    /// Append parity to `data`, interleaving the codewords byte by byte
+    pub fn encode(self, data: &[u8]) -> Vec<u8> {
+        let parity = self.parity();
+        if parity == 0 {
+            return data.to_vec();
+        }
+        let encoder = Encoder::new(parity);
+        let mut rest = data;
+        let codewords: Vec<Vec<u8>> = codeword_lens(data.len(), parity)
+            .map(|len| {
+                let (block, tail) = rest.split_at(len - parity);
+                rest = tail;
+                encoder.encode(block).to_vec()
+            })
+            .collect();
+
+        let mut out = vec![0; self.encoded_len(data.len())];
+        for (i, (codeword, position)) in interleaving(data.len(), parity).enumerate() {
+            out[i] = codewords[codeword][position];
+        }
+        out
+    }
+
+    /// Undo [`Ecc::encode`], repairing what the parity allows. Returns the data and
+    /// the number of bytes that had to be corrected.
+    pub fn decode(self, encoded: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+        let parity = self.parity();
+        if parity == 0 {
+            return Ok((encoded.to_vec(), 0));
+        }
+        let data_len = self.decoded_len(encoded.len()).ok_or_else(|| {
+            Error::CorruptHeader(format!("{} bytes is not a valid {self:?} error-corrected length", encoded.len()))
+        })?;
+
+        let mut codewords: Vec<Vec<u8>> = codeword_lens(data_len, parity).map(|len| vec![0; len]).collect();
+        for (&byte, (codeword, position)) in encoded.iter().zip(interleaving(data_len, parity)) {
+            codewords[codeword][position] = byte;
+        }
+
+        let decoder = Decoder::new(parity);
+        let mut data = Vec::with_capacity(data_len);
+        let mut corrected = 0;
+        for (i, codeword) in codewords.iter().enumerate() {
+            let (fixed, errors) = decoder.correct_err_count(codeword, None).map_err(|_| {
+                Error::CorruptPayload(format!("codeword {i} has more errors than {self:?} error correction can repair"))
+            })?;
+            data.extend_from_slice(fixed.data());
+            corrected += errors;
+        }
+        Ok((data, corrected))
+    }
+}
+
+/// Parity for a header, written after it when the payload uses error correction
+pub fn header_parity(header: &[u8; HEADER_LEN]) -> Vec<u8> {
+    Encoder::new(HEADER_PARITY).encode(header).ecc().to_vec()
+}
+
+/// Repair a header with the parity read after it; the corrected header and the
+/// number of bytes fixed, or `None` if it is beyond repair
+pub fn correct_header(header: &[u8], parity: &[u8]) -> Option<([u8; HEADER_LEN], usize)> {
+    let codeword = [header, parity].concat();
+    let (fixed, errors) = Decoder::new(HEADER_PARITY).correct_err_count(&codeword, None).ok()?;
+    Some((fixed.data().try_into().ok()?, errors))
+}
+
+fn codewords(data_len: usize, parity: usize) -> usize {
+    if parity == 0 { 0 } else { data_len.div_ceil(CODEWORD_LEN - parity) }
+}
+
+/// Lengths of the codewords `data_len` bytes are cut into: as even as possible,
+/// so they differ by at most one byte
+fn codeword_lens(data_len: usize, parity: usize) -> impl Iterator<Item = usize> + Clone {
+    let count = codewords(data_len, parity);
+    let base = data_len.checked_div(count).unwrap_or(0);
+    let longer = data_len.checked_rem(count).unwrap_or(0);
+    (0..count).map(move |i| base + usize::from(i < longer) + parity)
+}
+
+/// (codeword, position) of each encoded byte: the first byte of every codeword,
+/// then the second of every codeword, and so on
+fn interleaving(data_len: usize, parity: usize) -> impl Iterator<Item = (usize, usize)> {
+    let lens: Vec<usize> = codeword_lens(data_len, parity).collect();
+    let longest = lens.first().copied().unwrap_or(0);
+    (0..longest).flat_map(move |position| {
+        let lens = lens.clone();
+        (0..lens.len()).filter(move |&i| position < lens[i]).map(move |i| (i, position))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    #[test]
+    fn ecc_roundtrip() {
+        for ecc in [Ecc::None, Ecc::Low, Ecc::Medium, Ecc::High, Ecc::Max] {
+            for len in [0, 1, 100, 191, 192, 247, 248, 1000] {
+                let encoded = ecc.encode(&data(len));
+                assert_eq!(encoded.len(), ecc.encoded_len(len), "{ecc:?} {len}");
+                assert_eq!(ecc.decoded_len(encoded.len()), Some(len), "{ecc:?} {len}");
+                assert_eq!(ecc.decode(&encoded).unwrap(), (data(len), 0), "{ecc:?} {len}");
+            }
+            assert_eq!(Ecc::from_id(ecc.id()), Some(ecc));
+        }
+        // Not something any data length encodes to
+        assert_eq!(Ecc::Low.decoded_len(5), None);
+    }
+
+    #[test]
+    fn ecc_repairs_bursts() {
+        // Four codewords of 8 parity bytes each repair 4 bytes apiece; interleaving
+        // spreads a run of 16 damaged bytes evenly over all of them
+        let original = data(900);
+        let mut encoded = Ecc::Low.encode(&original);
+        for byte in &mut encoded[100..116] {
+            *byte ^= 0x5A;
+        }
+        assert_eq!(Ecc::Low.decode(&encoded).unwrap(), (original, 16));
+
+        encoded[116] ^= 1;
+        assert!(matches!(Ecc::Low.decode(&encoded), Err(Error::CorruptPayload(_))));
+    }
+
+    #[test]
+    fn ecc_repairs_header() {
+        let header: [u8; HEADER_LEN] = data(HEADER_LEN).try_into().unwrap();
+        let parity = header_parity(&header);
+        let mut damaged = header;
+        damaged[0] ^= 0xFF;
+        damaged[13] ^= 0x01;
+        assert_eq!(correct_header(&damaged, &parity), Some((header, 2)));
+        assert_eq!(correct_header(&[0xA5; HEADER_LEN], &parity), None);
+    }
+}
@@ -1,72 +1,405 @@
-use image::Pixel;
-use rand::{seq::SliceRandom, SeedableRng};
+use image::RgbaImage;
+use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
 
+use crate::Error;
+use crate::utils::Seed;
+use crate::crypto::{encrypt_message_with_kdf, Algorithm, kdf::KdfParams};
+use super::header::{Header, FLAG_FILE};
+use super::layout::Layout;
+use super::permutation::Permutation;
+use super::message::Message;
+use super::channels::{restore_color_type, Channels};
+use super::compression::Compression;
+use super::ecc::Ecc;
+use super::output::{LosslessFormat, OutputFormat};
+use super::verify::{compare_images, verify_saved, Verification};
+
+/// How a message is encrypted and placed in the cover image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbedOptions {
+    pub algorithm: Algorithm,
+    /// Key stretching for algorithms that use a KDF
+    pub kdf: KdfParams,
+    pub use_prng: bool,
+    pub seed: Option<Seed>,
+    pub strategy: EmbedStrategy,
+    /// Low bits per channel used for the payload (1–4)
+    pub depth: u8,
+    pub channels: Channels,
+    /// Applied to the plaintext before encryption; `Auto` picks whatever helps
+    pub compression: Compression,
+    /// Reed-Solomon parity added to the ciphertext
+    pub ecc: Ecc,
+}
+
+/// How a payload bit is written into a channel whose LSB differs from it.
+/// Both leave the bit in the LSB, so extraction is the same either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum EmbedStrategy {
+    /// Overwrite the LSB (`channel & 0xFE | bit`); fast, but equalizes
+    /// value pairs `2k`/`2k+1`, which the chi-square attack detects
+    #[default]
+    Replace,
+    /// LSB matching: randomly add or subtract 1, leaving the histogram intact
+    Match,
+}
+
+impl Default for EmbedOptions {
+    fn default() -> Self {
+        EmbedOptions {
+            algorithm: Algorithm::None,
+            kdf: KdfParams::default(),
+            use_prng: false,
+            seed: None,
+            strategy: EmbedStrategy::default(),
+            depth: 1,
+            channels: Channels::default(),
+            compression: Compression::default(),
+            ecc: Ecc::default(),
+        }
+    }
+}
+
+/// Encrypt `message` as described by `options` and embed it behind a header that
+/// records everything needed to extract it again with just the key
 pub fn embed_message(
     img: &image::DynamicImage,
-    message: &str,
-    use_prng: bool,
-    seed: Option<u64>,
+    message: &[u8],
+    key: &str,
+    options: &EmbedOptions,
+    out_path: &str,
+) -> Result<Verification, Error> {
+    embed_plaintext(img, &Message::new(message), key, options, out_path)
+}
+
+/// Embed the contents of the file at `path` along with its name and size
+pub fn embed_file(
+    img: &image::DynamicImage,
+    path: &str,
+    key: &str,
+    options: &EmbedOptions,
+    out_path: &str,
+) -> Result<Verification, Error> {
+    embed_plaintext(img, &Message::from_file(path)?, key, options, out_path)
+}
+
+/// Embed `message` and save the stego image to `out_path`, as [`embed_payload`] does
+pub fn embed_plaintext(
+    img: &image::DynamicImage,
+    message: &Message,
+    key: &str,
+    options: &EmbedOptions,
+    out_path: &str,
+) -> Result<Verification, Error> {
+    let (header, payload) = seal_for_image(img, message, key, options)?;
+    embed_payload(img, &header, &payload, options.seed, options.strategy, key, out_path)
+}
+
+/// In-memory counterpart of [`embed_plaintext`]: returns the stego image instead of
+/// saving it, so it can be post-processed or encoded with [`super::output::write_image`]
+pub fn embed_to_image(
+    img: &image::DynamicImage,
+    message: &Message,
+    key: &str,
+    options: &EmbedOptions,
+) -> Result<image::DynamicImage, Error> {
+    let (header, payload) = seal_for_image(img, message, key, options)?;
+    embed_payload_to_image(img, &header, &payload, options.seed, options.strategy, key)
+}
+
+/// [`seal_message`], with the depth and channels for embedding into the pixels of `img`
+fn seal_for_image(
+    img: &image::DynamicImage,
+    message: &Message,
+    key: &str,
+    options: &EmbedOptions,
+) -> Result<(Header, Vec<u8>), Error> {
+    let (mut header, payload) = seal_message(message, key, options)?;
+    header.set_depth(options.depth)?;
+    header.set_channels(options.channels.resolve(img));
+    Ok((header, payload))
+}
+
+/// Compress, encrypt and error-correct `message` as `options` say. Returns the header
+/// and the payload to embed after it; where it goes (depth, channels) is left to the caller.
+pub fn seal_message(message: &Message, key: &str, options: &EmbedOptions) -> Result<(Header, Vec<u8>), Error> {
+    let (compression, plaintext) = options.compression.apply(&message.encode())?;
+    let payload = encrypt_message_with_kdf(&plaintext, key, options.algorithm, &options.kdf)?;
+    let mut header = Header::new(&payload, options.algorithm, options.kdf.kdf, key, options.use_prng);
+    header.compression = compression;
+    if message.is_file() {
+        header.flags |= FLAG_FILE;
+    }
+    header.set_ecc(options.ecc);
+    Ok((header, options.ecc.encode(&payload)))
+}
+
+/// Embed `header` followed by `payload`, in PRNG order and in the channels the header says.
+/// `payload` is embedded as is, so it must already carry the error correction the header
+/// records. With [`EmbedStrategy::Match`] the ±1 choices are keyed with `key`.
+///
+/// `out_path` must name a lossless format; once written, the image is read back
+/// to check that it still carries the payload, see [`verify_saved`], and compared
+/// with `img` to report how much embedding changed it.
+pub fn embed_payload(
+    img: &image::DynamicImage,
+    header: &Header,
+    payload: &[u8],
+    seed: Option<Seed>,
+    strategy: EmbedStrategy,
+    key: &str,
     out_path: &str,
-) -> Result<(), String> {
+) -> Result<Verification, Error> {
+    let format = OutputFormat::from_path(out_path)?.lossless()?;
+    save(&embed_payload_to_image(img, header, payload, seed, strategy, key)?, out_path, format)?;
+    let (header, stego) = verify_saved(out_path, header, payload, seed)?;
+    Ok(Verification { header, metrics: compare_images(img, &stego)? })
+}
+
+/// In-memory counterpart of [`embed_payload`]
+pub fn embed_payload_to_image(
+    img: &image::DynamicImage,
+    header: &Header,
+    payload: &[u8],
+    seed: Option<Seed>,
+    strategy: EmbedStrategy,
+    key: &str,
+) -> Result<image::DynamicImage, Error> {
+    let source_color = img.color();
     let mut img = img.to_rgba8();
     let (width, height) = img.dimensions();
-    let bits = message_to_bits(message);
-
-    let length_prefix = (bits.len() as u32).to_be_bytes();
-    let mut full_bits = length_prefix
-        .iter()
-        .flat_map(|b| (0..8).rev().map(move |i| (b >> i) & 1 == 1))
-        .collect::<Vec<_>>();
-    full_bits.extend(bits);
-
-    let mut positions: Vec<(u32, u32)> = (0..width)
-        .flat_map(|x| (0..height).map(move |y| (x, y)))
-        .collect();
-
-    if use_prng {
-        if let Some(s) = seed {
-            let mut rng = ChaCha20Rng::seed_from_u64(s);
-            positions.shuffle(&mut rng);
-        } else {
-            return Err("PRNG selected but no seed provided".to_string());
-        }
+    let pixels = width as usize * height as usize;
+    Layout::from_header(pixels, header).check(payload.len())?;
+
+    let plan = Plan {
+        header: header.to_embedded_bytes(),
+        payload,
+        depth: header.depth(),
+        channels: header.channels(),
+        permutation: Permutation::for_header(header, pixels, seed)?,
+        width: width as usize,
+        height: height as usize,
+        strategy,
+        matching_seed: matching_seed(key, header),
+    };
+    plan.write(&mut img);
+
+    Ok(restore_color_type(img, source_color, plan.channels))
+}
+
+/// Pixels embedded as one unit. Each chunk draws its ±1 choices from its own RNG
+/// stream, so chunks can be written in any order, or in parallel, with the same result.
+const CHUNK_PIXELS: usize = 1 << 14;
+
+/// Where every bit of the container goes; any channel slot can be worked out on its own
+struct Plan<'a> {
+    /// Header and its parity, see [`Header::to_embedded_bytes`]
+    header: Vec<u8>,
+    payload: &'a [u8],
+    depth: u8,
+    channels: Channels,
+    permutation: Permutation,
+    width: usize,
+    height: usize,
+    strategy: EmbedStrategy,
+    matching_seed: [u8; 32],
+}
+
+impl Plan<'_> {
+    /// Channel slots the container fills
+    fn slots(&self) -> usize {
+        self.header_slots() + (self.payload.len() * 8).div_ceil(self.depth as usize)
+    }
+
+    fn header_slots(&self) -> usize {
+        self.header.len() * 8
+    }
+
+    fn chunks(&self) -> usize {
+        self.slots().div_ceil(self.channels.count()).div_ceil(CHUNK_PIXELS)
     }
 
-    if full_bits.len() > positions.len() * 4 {
-        return Err("Message too long to fit in image".to_string());
+    /// Bits written into `slot`, and how many. The header goes in at one bit per
+    /// channel, the payload at the recorded depth; a short final chunk of payload
+    /// bits is padded with zeros at the low end.
+    fn bits(&self, slot: usize) -> (u8, u8) {
+        let bit = |bytes: &[u8], i: usize| (bytes[i / 8] >> (7 - i % 8)) & 1;
+        if slot < self.header_slots() {
+            return (bit(&self.header, slot), 1);
+        }
+        let depth = self.depth as usize;
+        let first = (slot - self.header_slots()) * depth;
+        let count = depth.min(self.payload.len() * 8 - first);
+        let value = (first..first + count).fold(0u8, |acc, i| (acc << 1) | bit(self.payload, i));
+        (value << (depth - count), self.depth)
     }
 
-    let mut bit_iter = full_bits.into_iter();
+    /// Embed `chunk`. Each channel it changes is handed to `update` as an offset into
+    /// the raw RGBA buffer, with the function from its old value to its new one.
+    fn write_chunk(&self, chunk: usize, mut update: impl FnMut(usize, &mut dyn FnMut(u8) -> u8)) {
+        let mut rng = ChaCha20Rng::from_seed(self.matching_seed);
+        rng.set_stream(chunk as u64);
+
+        let slots = self.slots();
+        let count = self.channels.count();
+        let start = chunk * CHUNK_PIXELS;
+        let end = (start + CHUNK_PIXELS).min(slots.div_ceil(count));
 
-    for (x, y) in positions {
-        let mut px = *img.get_pixel(x, y);
-        let channels = px.channels_mut();
+        for step in start..end {
+            // Pixels are numbered x-major, the buffer is row-major
+            let index = self.permutation.get(step);
+            let (x, y) = (index / self.height, index % self.height);
+            let base = (y * self.width + x) * 4;
 
-        for channel in channels.iter_mut().take(4) {
-            if let Some(bit) = bit_iter.next() {
-                *channel = (*channel & 0xFE) | (bit as u8);
-            } else {
-                break;
+            for (i, &channel) in self.channels.indices().iter().enumerate() {
+                let slot = step * count + i;
+                if slot >= slots {
+                    break;
+                }
+                let (bits, depth) = self.bits(slot);
+                update(base + channel, &mut |value| match self.strategy {
+                    EmbedStrategy::Replace => replace_bits(value, bits, depth),
+                    EmbedStrategy::Match => match_bits(value, bits, depth, &mut rng),
+                });
             }
         }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn write(&self, img: &mut RgbaImage) {
+        self.write_serial(img);
+    }
+
+    /// Embed the chunks on every core, each reading from a copy of the original
+    /// pixels and applying its changes as soon as it is done; byte-identical to
+    /// [`Plan::write_serial`], since no two chunks touch the same pixel
+    #[cfg(feature = "parallel")]
+    fn write(&self, img: &mut RgbaImage) {
+        use rayon::prelude::*;
+        use std::sync::Mutex;
+
+        if self.chunks() <= 1 {
+            return self.write_serial(img);
+        }
+        let original = img.as_raw().clone();
+        let raw = Mutex::new(&mut **img);
+        (0..self.chunks()).into_par_iter().for_each_init(Vec::new, |writes, chunk| {
+            writes.clear();
+            self.write_chunk(chunk, |offset, embed| writes.push((offset, embed(original[offset]))));
+            let mut raw = raw.lock().unwrap();
+            for &(offset, value) in writes.iter() {
+                raw[offset] = value;
+            }
+        });
+    }
 
-        img.put_pixel(x, y, px);
-        if bit_iter.next().is_none() {
-            break;
+    fn write_serial(&self, img: &mut RgbaImage) {
+        let raw: &mut [u8] = img;
+        for chunk in 0..self.chunks() {
+            self.write_chunk(chunk, |offset, embed| raw[offset] = embed(raw[offset]));
         }
     }
+}
 
-    // Save the image to the specified output path
-    img.save(out_path)
-        .map_err(|e| format!("Failed to save image: {}", e))?;
+/// Overwrite the `depth` low bits of `value` with `bits`
+fn replace_bits(value: u8, bits: u8, depth: u8) -> u8 {
+    let mask = (1u8 << depth) - 1;
+    (value & !mask) | bits
+}
+
+/// The value nearest to `value` whose `depth` low bits are `bits`, staying within
+/// 0..=255; ties (always ±1 at depth 1) are broken with the keyed RNG
+fn match_bits(value: u8, bits: u8, depth: u8, rng: &mut ChaCha20Rng) -> u8 {
+    let target = replace_bits(value, bits, depth);
+    if target == value {
+        return value;
+    }
+    let step = 1i16 << depth;
+    let candidates = [target as i16 - step, target as i16, target as i16 + step];
+    let distance = |c: i16| (c - value as i16).abs();
+    let in_range = candidates.into_iter().filter(|c| (0..=255).contains(c));
+    let nearest = in_range.clone().map(distance).min().unwrap();
+    let ties: Vec<i16> = in_range.filter(|&c| distance(c) == nearest).collect();
 
-    Ok(())
+    let chosen = if ties.len() > 1 && rng.random::<bool>() { ties[1] } else { ties[0] };
+    chosen as u8
 }
 
-fn message_to_bits(message: &str) -> Vec<bool> {
-    message
-        .bytes()
-        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
-        .collect()
+/// Seed for the ±1 choices, keyed with the passphrase and bound to this payload
+fn matching_seed(key: &str, header: &Header) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"cryimg-lsb-matching-v1");
+    hasher.update(key.as_bytes());
+    hasher.update(header.to_bytes());
+    hasher.finalize().into()
+}
+
+fn save(img: &image::DynamicImage, out_path: &str, format: LosslessFormat) -> Result<(), Error> {
+    // Save the image to the specified output path
+    img.save_with_format(out_path, format.into())
+        .map_err(|e| Error::Io(format!("Failed to save image: {}", e)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::kdf::Kdf;
+
+    /// A plan spanning several chunks, in Feistel order with LSB matching
+    fn plan<'a>(img: &RgbaImage, payload: &'a [u8]) -> Plan<'a> {
+        let mut header = Header::new(payload, Algorithm::None, Kdf::None, "", true);
+        header.set_depth(2).unwrap();
+        header.set_channels(Channels::Rgb);
+        Plan {
+            header: header.to_embedded_bytes(),
+            payload,
+            depth: 2,
+            channels: Channels::Rgb,
+            permutation: Permutation::for_header(&header, img.len() / 4, Some(Seed::new("chunks"))).unwrap(),
+            width: img.width() as usize,
+            height: img.height() as usize,
+            strategy: EmbedStrategy::Match,
+            matching_seed: matching_seed("key", &header),
+        }
+    }
+
+    fn cover() -> RgbaImage {
+        crate::stegano::test_util::cover(256, 256).to_rgba8()
+    }
+
+    #[test]
+    fn embed_chunks_are_independent() {
+        let payload: Vec<u8> = (0..40_000).map(|i| (i * 7 % 251) as u8).collect();
+        let cover = cover();
+        let plan = plan(&cover, &payload);
+        assert!(plan.chunks() > 3);
+
+        let mut serial = cover.clone();
+        plan.write_serial(&mut serial);
+
+        // Chunks only read pixels they write themselves, so the order they run in does not matter
+        let mut reversed = cover.clone();
+        let raw: &mut [u8] = &mut reversed;
+        for chunk in (0..plan.chunks()).rev() {
+            plan.write_chunk(chunk, |offset, embed| raw[offset] = embed(raw[offset]));
+        }
+        assert_eq!(serial, reversed);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn embed_parallel_matches_serial() {
+        let payload: Vec<u8> = (0..40_000).map(|i| (i * 7 % 251) as u8).collect();
+        let cover = cover();
+        let plan = plan(&cover, &payload);
+
+        let mut serial = cover.clone();
+        plan.write_serial(&mut serial);
+        let mut parallel = cover.clone();
+        plan.write(&mut parallel);
+        assert_eq!(serial, parallel);
+    }
 }
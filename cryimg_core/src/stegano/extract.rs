@@ -1,71 +1,684 @@
-use rand::{seq::SliceRandom, SeedableRng};
-use rand_chacha::ChaCha20Rng;
-use image::DynamicImage;
+use std::ops::Range;
 
+use image::{DynamicImage, GenericImageView};
+
+use crate::Error;
+use crate::utils::Seed;
+use crate::crypto::{decrypt_message_with_kdf, dearmor};
+use super::ecc::HEADER_PARITY;
+use super::header::Header;
+use super::layout::{Layout, HEADER_SLOTS};
+use super::message::Message;
+use super::channels::Channels;
+use super::permutation::Permutation;
+
+/// Where to look for the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtractOptions {
+    pub use_prng: bool,
+    pub seed: Option<Seed>,
+    /// Also try the full shuffle images embedded with `--prng` used before the
+    /// Feistel order. It takes 8 bytes per pixel, so it is only built when asked for.
+    pub legacy_order: bool,
+}
+
+impl ExtractOptions {
+    pub fn new(use_prng: bool, seed: Option<Seed>) -> Self {
+        ExtractOptions { use_prng, seed, legacy_order: false }
+    }
+}
+
+/// Read just the container header, e.g. to inspect an image without the key
+pub fn read_header(
+    img: &DynamicImage,
+    use_prng: bool,
+    seed: Option<Seed>,
+) -> Result<Header, Error> {
+    read_header_with_options(img, &ExtractOptions::new(use_prng, seed))
+}
+
+/// [`read_header`] with all of the [`ExtractOptions`]
+pub fn read_header_with_options(img: &DynamicImage, options: &ExtractOptions) -> Result<Header, Error> {
+    Stream::locate(img, options).map(|(_, header, _)| header)
+}
+
+/// Extract the payload and its validated container header. Reads the header,
+/// then exactly `payload_len` bytes, and never touches the rest of the image.
+/// Also returns how many bytes error correction repaired, in the header and payload.
+pub fn extract_payload(
+    img: &DynamicImage,
+    use_prng: bool,
+    seed: Option<Seed>,
+) -> Result<(Header, Vec<u8>, usize), Error> {
+    extract_payload_with_options(img, &ExtractOptions::new(use_prng, seed))
+}
+
+/// [`extract_payload`] with all of the [`ExtractOptions`]
+pub fn extract_payload_with_options(
+    img: &DynamicImage,
+    options: &ExtractOptions,
+) -> Result<(Header, Vec<u8>, usize), Error> {
+    let (stream, header, header_corrected) = Stream::locate(img, options)?;
+
+    let payload_len = header.payload_len as usize;
+    if !Layout::from_header(stream.len(), &header).fits(payload_len) {
+        return Err(Error::CorruptHeader(format!(
+            "payload length ({payload_len} bytes) exceeds image capacity"
+        )));
+    }
+    let payload = stream.payload(&header).ok_or(Error::NoPayload)?;
+    let (payload, payload_corrected) = check_payload(&header, &payload)?;
+
+    Ok((header, payload, header_corrected + payload_corrected))
+}
+
+/// Undo the error correction of a payload as read from a cover and check it against
+/// the header's checksum; also returns how many bytes had to be repaired
+pub fn check_payload(header: &Header, payload: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let (payload, corrected) = header.ecc.decode(payload)?;
+    header.verify(&payload)?;
+    Ok((payload, corrected))
+}
+
+/// Extract and decrypt a message; the algorithm is read from the header,
+/// so only the key (and the seed for PRNG order) has to be supplied
 pub fn extract_message(
     img: &DynamicImage,
+    key: &str,
     use_prng: bool,
-    seed: Option<u64>,
-) -> Result<String, String> {
-    let img = img.to_rgba8();
-    let (width, height) = img.dimensions();
-
-    let mut positions: Vec<(u32, u32)> = (0..width)
-        .flat_map(|x| (0..height).map(move |y| (x, y)))
-        .collect();
-
-    if use_prng {
-        if let Some(s) = seed {
-            let mut rng = ChaCha20Rng::seed_from_u64(s);
-            positions.shuffle(&mut rng);
-        } else {
-            return Err("PRNG selected but no seed provided".to_string());
-        }
+    seed: Option<Seed>,
+) -> Result<(Header, Message), Error> {
+    extract_message_corrected(img, key, use_prng, seed).map(|(header, message, _)| (header, message))
+}
+
+/// [`extract_message`], also returning how many bytes error correction repaired
+pub fn extract_message_corrected(
+    img: &DynamicImage,
+    key: &str,
+    use_prng: bool,
+    seed: Option<Seed>,
+) -> Result<(Header, Message, usize), Error> {
+    extract_message_with_options(img, key, &ExtractOptions::new(use_prng, seed))
+}
+
+/// [`extract_message_corrected`] with all of the [`ExtractOptions`]
+pub fn extract_message_with_options(
+    img: &DynamicImage,
+    key: &str,
+    options: &ExtractOptions,
+) -> Result<(Header, Message, usize), Error> {
+    let (header, payload, corrected) = extract_payload_with_options(img, options)?;
+    Ok((header, open_payload(&header, payload, key)?, corrected))
+}
+
+/// Decrypt and decompress a checked payload, see [`check_payload`]
+pub fn open_payload(header: &Header, payload: Vec<u8>, key: &str) -> Result<Message, Error> {
+    header.verify_key(key)?;
+
+    let payload = if header.armored() { dearmor(&payload)? } else { payload };
+    let plaintext = decrypt_message_with_kdf(&payload, key, header.algorithm, header.kdf)
+        .map_err(|e| {
+            // The checksum already vouched for the data, so a failure here means the key is wrong
+            if header.algorithm.uses_kdf() {
+                Error::BadKey(format!(
+                    "Wrong key: the supplied key does not match the one used to embed this image ({e})"
+                ))
+            } else {
+                e
+            }
+        })?;
+    let plaintext = header.compression.decompress(&plaintext)?;
+    Message::decode(plaintext, header.is_file())
+}
+
+/// Extract a message embedded before the container header existed,
+/// i.e. a bare 32-bit bit-length prefix followed by the message bits.
+/// Also returns a warning for the user when the stored length looks implausible.
+pub fn extract_legacy_message(
+    img: &DynamicImage,
+    use_prng: bool,
+    seed: Option<Seed>,
+) -> Result<(String, Option<String>), Error> {
+    // Legacy images always used all four channels
+    let stream = Stream::legacy(img, use_prng, seed)?;
+    let available = stream.len() * Channels::Rgba.count();
+    let mut bits = Bits::new(stream.values(Channels::Rgba), 1);
+
+    let prefix = read_bytes(&mut bits, 32).ok_or(Error::NoPayload)?;
+    let msg_len = u32::from_be_bytes(prefix.try_into().unwrap()) as usize;
+
+    let warning = (msg_len > 1_000_000_000)
+        .then(|| format!("Unrealistic message length detected: {msg_len} bits. Proceeding anyway..."));
+
+    if 32 + msg_len > available {
+        return Err(Error::CorruptHeader(format!(
+            "message length ({msg_len} bits) exceeds available data ({} bits)",
+            available - 32
+        )));
     }
+    let message = read_bytes(&mut bits, msg_len).ok_or(Error::NoPayload)?;
+    Ok((message.into_iter().map(|b| b as char).collect(), warning))
+}
+
+/// Payload bytes read as one unit by the parallel path
+#[cfg(feature = "parallel")]
+const CHUNK_BYTES: usize = 1 << 16;
 
-    let mut bits = Vec::new();
+/// The cover's channel values in embedding order, read straight from the image
+/// as they are needed rather than copied or unpacked up front
+struct Stream<'a> {
+    img: &'a DynamicImage,
+    permutation: Permutation,
+}
+
+impl<'a> Stream<'a> {
+    /// Find the header in the Feistel order, or, with [`ExtractOptions::legacy_order`],
+    /// in the full shuffle that images embedded before it used. Also returns the
+    /// number of header bytes its parity repaired.
+    fn locate(img: &'a DynamicImage, options: &ExtractOptions) -> Result<(Self, Header, usize), Error> {
+        let len = img.width() as usize * img.height() as usize;
+        let seed = match (options.use_prng, options.seed) {
+            (false, _) => {
+                let stream = Stream { img, permutation: Permutation::Identity };
+                return stream.header().map(|(header, corrected)| (stream, header, corrected));
+            }
+            (true, None) => return Err(Error::MissingSeed),
+            (true, Some(seed)) => seed,
+        };
 
-    for (x, y) in &positions {
-        let px = img.get_pixel(*x, *y);
-        for i in 0..4 {
-            bits.push((px[i] & 1) == 1);
+        let mut first_error = None;
+        for permutation in Self::orders(len, seed, options.legacy_order) {
+            let feistel = matches!(permutation, Permutation::Feistel(_));
+            let stream = Stream { img, permutation };
+            match stream.header() {
+                Ok((header, corrected)) if header.uses_feistel() == feistel => return Ok((stream, header, corrected)),
+                Ok(_) | Err(Error::NoPayload) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
         }
+        Err(first_error.unwrap_or(Error::NoPayload))
     }
 
-    let len_bits = &bits[0..32];
-    let msg_len = bits_to_u32(len_bits)? as usize;
+    /// PRNG orders to try, built one at a time so that a miss in the Feistel
+    /// order only pays for the shuffle when it was asked for
+    fn orders(len: usize, seed: Seed, legacy_order: bool) -> impl Iterator<Item = Permutation> {
+        std::iter::once_with(move || Permutation::feistel(len, &seed))
+            .chain(legacy_order.then_some(()).into_iter().map(move |()| Permutation::shuffle(len, &seed)))
+    }
+
+    /// Headerless images always used the full shuffle
+    fn legacy(img: &'a DynamicImage, use_prng: bool, seed: Option<Seed>) -> Result<Self, Error> {
+        let len = img.width() as usize * img.height() as usize;
+        let permutation = match (use_prng, seed) {
+            (false, _) => Permutation::Identity,
+            (true, None) => return Err(Error::MissingSeed),
+            (true, Some(seed)) => Permutation::shuffle(len, &seed),
+        };
+        Ok(Stream { img, permutation })
+    }
+
+    /// Number of pixels
+    fn len(&self) -> usize {
+        self.img.width() as usize * self.img.height() as usize
+    }
+
+    /// Values of the selected channels, in embedding order
+    fn values(&self, channels: Channels) -> impl Iterator<Item = u8> + '_ {
+        self.values_from(channels, 0)
+    }
 
-    if msg_len > 1_000_000_000 {
-        eprintln!("⚠️ Warning: Unrealistic message length detected: {} bits. Proceeding anyway...", msg_len);
+    /// Values of the selected channels from channel slot `slot` on
+    fn values_from(&self, channels: Channels, slot: usize) -> impl Iterator<Item = u8> + '_ {
+        let height = self.img.height() as usize;
+        let count = channels.count();
+        (slot / count..self.len())
+            .flat_map(move |i| {
+                let index = self.permutation.get(i);
+                let px = self.img.get_pixel((index / height) as u32, (index % height) as u32);
+                channels.indices().iter().map(move |&c| px[c])
+            })
+            .skip(slot % count)
     }
 
-    if 32 + msg_len > bits.len() {
-        return Err(format!(
-            "Message length ({msg_len} bits) exceeds available data ({} bits)",
-            bits.len() - 32
-        ));
+    /// Payload bytes in `bytes`; past the header, every channel holds `depth` payload bits
+    fn payload_range(&self, header: &Header, bytes: Range<usize>) -> Option<Vec<u8>> {
+        let depth = header.depth() as usize;
+        let first_bit = bytes.start * 8;
+        let header_slots = Layout::from_header(self.len(), header).header_slots();
+        let values = self.values_from(header.channels(), header_slots + first_bit / depth);
+        let mut bits = Bits::new(values, header.depth()).skip(first_bit % depth);
+        read_bytes(&mut bits, bytes.len() * 8)
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn payload(&self, header: &Header) -> Option<Vec<u8>> {
+        self.payload_range(header, 0..header.payload_len as usize)
+    }
+
+    /// Read chunks of the payload on every core; the same bytes as the serial path
+    #[cfg(feature = "parallel")]
+    fn payload(&self, header: &Header) -> Option<Vec<u8>> {
+        use rayon::prelude::*;
+
+        let len = header.payload_len as usize;
+        let chunks: Option<Vec<Vec<u8>>> = (0..len.div_ceil(CHUNK_BYTES))
+            .into_par_iter()
+            .map(|chunk| self.payload_range(header, chunk * CHUNK_BYTES..len.min((chunk + 1) * CHUNK_BYTES)))
+            .collect();
+        Some(chunks?.concat())
+    }
+
+    /// The header sits in the LSBs of the first channels of whichever selection was
+    /// used; try each and accept the one whose header names that same selection.
+    /// Parity, if the payload has error correction, follows at one bit per channel.
+    fn header(&self) -> Result<(Header, usize), Error> {
+        let mut first_error = None;
+        for channels in Channels::CONCRETE {
+            let mut bits = Bits::new(self.values(channels), 1);
+            let Some(bytes) = read_bytes(&mut bits, HEADER_SLOTS) else {
+                continue;
+            };
+            let parity = read_bytes(&mut bits, HEADER_PARITY * 8);
+            match Header::from_embedded_bytes(&bytes, parity.as_deref()) {
+                Ok((header, corrected)) if header.channels() == channels => return Ok((header, corrected)),
+                Ok(_) | Err(Error::NoPayload) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error.unwrap_or(Error::NoPayload))
     }
-    let msg_bits = &bits[32..(32 + msg_len)];
-    Ok(bits_to_message(msg_bits))
 }
 
-fn bits_to_u32(bits: &[bool]) -> Result<u32, String> {
-    if bits.len() != 32 {
-        return Err("Invalid length prefix".to_string());
+/// Bits carried by a run of channel values: the `depth` low bits of each, MSB first
+struct Bits<I> {
+    values: I,
+    depth: u8,
+    value: u8,
+    left: u8,
+}
+
+impl<I: Iterator<Item = u8>> Bits<I> {
+    fn new(values: I, depth: u8) -> Self {
+        Bits { values, depth, value: 0, left: 0 }
     }
-    let mut value = 0u32;
-    for bit in bits {
-        value = (value << 1) | (*bit as u32);
+}
+
+impl<I: Iterator<Item = u8>> Iterator for Bits<I> {
+    type Item = bool;
+
+    fn next(&mut self) -> Option<bool> {
+        if self.left == 0 {
+            self.value = self.values.next()?;
+            self.left = self.depth;
+        }
+        self.left -= 1;
+        Some((self.value >> self.left) & 1 == 1)
     }
-    Ok(value)
 }
 
-fn bits_to_message(bits: &[bool]) -> String {
-    bits.chunks(8)
-        .map(|byte| {
-            byte.iter()
-                .fold(0u8, |acc, &b| (acc << 1) | (b as u8))
-        })
-        .map(|b| b as char)
-        .collect::<String>()
+/// Pack the next `count` bits into bytes, MSB first (a short last byte keeps its
+/// bits at the low end); `None` if the image runs out first
+fn read_bytes(bits: &mut impl Iterator<Item = bool>, count: usize) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(count.div_ceil(8));
+    let mut remaining = count;
+    while remaining > 0 {
+        let chunk = remaining.min(8);
+        let mut byte = 0u8;
+        for _ in 0..chunk {
+            byte = (byte << 1) | bits.next()? as u8;
+        }
+        bytes.push(byte);
+        remaining -= chunk;
+    }
+    Some(bytes)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Algorithm, kdf::{Kdf, KdfParams, KdfPreset}};
+    use crate::stegano::ecc::Ecc;
+    use crate::stegano::embed::{embed_file, embed_message, embed_payload_to_image, embed_to_image, EmbedOptions, EmbedStrategy};
+    use crate::stegano::header::FLAG_FEISTEL;
+    use crate::stegano::message::Message;
+    use crate::stegano::output::{write_image, LosslessFormat};
+    use crate::stegano::test_util::{cover, temp_path};
+    use image::DynamicImage;
+
+    fn options(algorithm: Algorithm, seed: Option<Seed>) -> EmbedOptions {
+        EmbedOptions {
+            algorithm,
+            kdf: KdfParams::new(Kdf::Argon2id, KdfPreset::Interactive),
+            use_prng: seed.is_some(),
+            seed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn extract_roundtrip() {
+        let path = temp_path("roundtrip.png");
+        embed_message(&cover(16, 16), b"Hello, World!", "", &options(Algorithm::None, None), &path).unwrap();
+        let stego = image::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let (header, payload, corrected) = extract_payload(&stego, false, None).unwrap();
+        assert_eq!(header.algorithm, Algorithm::None);
+        assert!(!header.uses_prng());
+        assert_eq!(payload, b"Hello, World!");
+        assert_eq!(corrected, 0);
+    }
+
+    #[test]
+    fn extract_roundtrip_prng() {
+        let path = temp_path("roundtrip_prng.png");
+        embed_message(&cover(16, 16), b"Shuffled", "key", &options(Algorithm::Xor, Some(Seed::new("42"))), &path).unwrap();
+        let stego = image::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let (header, message) = extract_message(&stego, "key", true, Some(Seed::new("42"))).unwrap();
+        assert_eq!(header.algorithm, Algorithm::Xor);
+        assert_eq!(message.data, b"Shuffled");
+        assert!(extract_message(&stego, "key", true, Some(Seed::new("43"))).is_err());
+    }
+
+    #[test]
+    fn extract_lsb_matching() {
+        let message = Message::new(b"Matched, not replaced".to_vec());
+        let cover = cover(24, 24);
+        let options = EmbedOptions { strategy: EmbedStrategy::Match, ..options(Algorithm::Xor, Some(Seed::new("m"))) };
+        let stego = embed_to_image(&cover, &message, "k", &options).unwrap();
+
+        // Every channel moved by at most one
+        for (a, b) in cover.to_rgba8().pixels().zip(stego.to_rgba8().pixels()) {
+            for c in 0..4 {
+                assert!(a[c].abs_diff(b[c]) <= 1);
+            }
+        }
+        let (_, extracted) = extract_message(&stego, "k", true, Some(Seed::new("m"))).unwrap();
+        assert_eq!(extracted, message);
+    }
+
+    #[test]
+    fn extract_depths() {
+        let message = Message::new((0..=255).collect::<Vec<u8>>());
+        let cover = cover(20, 20);
+        for strategy in [EmbedStrategy::Replace, EmbedStrategy::Match] {
+            for depth in 1..=4 {
+                let options = EmbedOptions { depth, strategy, ..options(Algorithm::None, Some(Seed::new("d"))) };
+                let result = embed_to_image(&cover, &message, "", &options);
+                if depth == 1 {
+                    // 1200 RGB channels hold the 21-byte header and 129 payload bytes
+                    assert!(matches!(result, Err(Error::CapacityExceeded { needed: 277, available: 150 })));
+                    continue;
+                }
+                let stego = result.unwrap();
+                for (a, b) in cover.to_rgba8().pixels().zip(stego.to_rgba8().pixels()) {
+                    for c in 0..4 {
+                        assert!(a[c].abs_diff(b[c]) < 1 << depth, "{strategy:?} {depth}");
+                    }
+                }
+                let (header, extracted) = extract_message(&stego, "", true, Some(Seed::new("d"))).unwrap();
+                assert_eq!(header.depth(), depth);
+                assert_eq!(extracted, message, "{strategy:?} {depth}");
+            }
+        }
+    }
+
+    #[test]
+    fn extract_channel_selection() {
+        let message = Message::new(b"Spread over a few channels".to_vec());
+        let cover = cover(32, 32);
+        for channels in Channels::CONCRETE {
+            let options = EmbedOptions { channels, ..options(Algorithm::Xor, Some(Seed::new("c"))) };
+            let stego = embed_to_image(&cover, &message, "k", &options).unwrap();
+
+            // Channels outside the selection are left untouched
+            for (a, b) in cover.to_rgba8().pixels().zip(stego.to_rgba8().pixels()) {
+                for c in (0..4).filter(|c| !channels.indices().contains(c)) {
+                    assert_eq!(a[c], b[c], "{channels:?}");
+                }
+            }
+            let (header, extracted) = extract_message(&stego, "k", true, Some(Seed::new("c"))).unwrap();
+            assert_eq!(header.channels(), channels);
+            assert_eq!(extracted, message, "{channels:?}");
+        }
+    }
+
+    #[test]
+    fn extract_keeps_cover_color_type() {
+        let message = Message::new(b"No alpha here".to_vec());
+        let rgb = DynamicImage::ImageRgb8(cover(16, 16).to_rgb8());
+        let stego = embed_to_image(&rgb, &message, "", &options(Algorithm::None, None)).unwrap();
+        assert_eq!(stego.color(), image::ColorType::Rgb8);
+        assert_eq!(extract_message(&stego, "", false, None).unwrap().1, message);
+
+        // A translucent cover keeps its alpha and `Auto` writes into it
+        let mut translucent = cover(16, 16).to_rgba8();
+        translucent.get_pixel_mut(0, 0)[3] = 0;
+        let stego = embed_to_image(&DynamicImage::ImageRgba8(translucent), &message, "", &options(Algorithm::None, None)).unwrap();
+        assert_eq!(stego.color(), image::ColorType::Rgba8);
+        let (header, extracted) = extract_message(&stego, "", false, None).unwrap();
+        assert_eq!(header.channels(), Channels::Rgba);
+        assert_eq!(extracted, message);
+    }
+
+    #[test]
+    fn extract_bits_unpack_depth() {
+        let mut bits = Bits::new([0b101, 0b011, 0b110].into_iter(), 3);
+        assert_eq!(read_bytes(&mut bits, 8), Some(vec![0b1010_1111]));
+        assert_eq!(read_bytes(&mut bits, 1), Some(vec![0]));
+        assert_eq!(read_bytes(&mut bits, 1), None);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn extract_parallel_matches_serial() {
+        let message = Message::new((0..150_000u32).map(|i| (i % 253) as u8).collect::<Vec<_>>());
+        let options = EmbedOptions { depth: 4, ..options(Algorithm::None, Some(Seed::new("p"))) };
+        let stego = embed_to_image(&cover(320, 320), &message, "", &options).unwrap();
+
+        let (stream, header, _) = Stream::locate(&stego, &ExtractOptions::new(true, Some(Seed::new("p")))).unwrap();
+        let serial = stream.payload_range(&header, 0..header.payload_len as usize).unwrap();
+        assert_eq!(stream.payload(&header).unwrap(), serial);
+        assert_eq!(serial, message.data);
+    }
+
+    #[test]
+    fn extract_legacy_format() {
+        // Bit-length prefix, then the message, one bit in each RGBA channel in x-major order
+        let message = b"old school";
+        let prefix = ((message.len() * 8) as u32).to_be_bytes();
+        let bits: Vec<u8> = prefix.iter().chain(message)
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+            .collect();
+
+        let mut img = cover(16, 16).to_rgba8();
+        let mut bits = bits.into_iter();
+        'fill: for x in 0..16 {
+            for y in 0..16 {
+                for c in 0..4 {
+                    let Some(bit) = bits.next() else { break 'fill };
+                    let px = img.get_pixel_mut(x, y);
+                    px[c] = (px[c] & 0xFE) | bit;
+                }
+            }
+        }
+
+        let (extracted, warning) = extract_legacy_message(&DynamicImage::ImageRgba8(img), false, None).unwrap();
+        assert_eq!(extracted, "old school");
+        assert_eq!(warning, None);
+    }
+
+    #[test]
+    fn extract_legacy_seed() {
+        let stego = embed_to_image(
+            &cover(16, 16),
+            &Message::new(b"Old order".to_vec()),
+            "",
+            &options(Algorithm::None, Some(Seed::legacy("42"))),
+        ).unwrap();
+
+        assert!(extract_message(&stego, "", true, Some(Seed::new("42"))).is_err());
+        let (_, message) = extract_message(&stego, "", true, Some(Seed::legacy("42"))).unwrap();
+        assert_eq!(message.data, b"Old order");
+    }
+
+    #[test]
+    fn extract_shuffled_order() {
+        // Images embedded before the Feistel order carry a fully shuffled one
+        let payload = b"Shuffled the old way";
+        let mut header = Header::new(payload, Algorithm::None, Kdf::None, "", true);
+        header.flags &= !FLAG_FEISTEL;
+        header.set_channels(Channels::Rgb);
+        let stego = embed_payload_to_image(&cover(16, 16), &header, payload, Some(Seed::new("s")), EmbedStrategy::Replace, "").unwrap();
+
+        assert_eq!(extract_message(&stego, "", true, Some(Seed::new("s"))).unwrap_err(), Error::NoPayload);
+        let options = ExtractOptions { legacy_order: true, ..ExtractOptions::new(true, Some(Seed::new("s"))) };
+        let (header, message, _) = extract_message_with_options(&stego, "", &options).unwrap();
+        assert!(!header.uses_feistel());
+        assert_eq!(message.data, payload);
+    }
+
+    #[test]
+    fn extract_miss_skips_shuffle() {
+        // The full shuffle is only materialized when legacy order was asked for
+        let seed = Seed::new("s");
+        let orders: Vec<_> = Stream::orders(1 << 20, seed, false).collect();
+        assert!(matches!(orders[..], [Permutation::Feistel(_)]));
+        let orders: Vec<_> = Stream::orders(16, seed, true).collect();
+        assert!(matches!(orders[..], [Permutation::Feistel(_), Permutation::Shuffle(_)]));
+    }
+
+    #[test]
+    fn extract_armored_version_2() {
+        // Version 2 containers stored XOR and AES output as base64 text
+        let cipher = crate::crypto::encrypt_message(b"Armored", "key", Algorithm::Xor).unwrap();
+        let payload = crate::crypto::armor(&cipher).into_bytes();
+        let mut header = Header::new(&payload, Algorithm::Xor, Kdf::None, "key", false);
+        header.version = 2;
+        header.set_channels(Channels::Rgb);
+        let stego = embed_payload_to_image(&cover(16, 16), &header, &payload, None, EmbedStrategy::Replace, "").unwrap();
+
+        let (header, message) = extract_message(&stego, "key", false, None).unwrap();
+        assert!(header.armored());
+        assert_eq!(message.data, b"Armored");
+    }
+
+    #[test]
+    fn extract_key_derived_order() {
+        let seed = Seed::from_key("one secret").unwrap();
+        let stego = embed_to_image(
+            &cover(24, 24),
+            &Message::new(b"Hidden twice".to_vec()),
+            "one secret",
+            &options(Algorithm::AesGcm, Some(seed)),
+        ).unwrap();
+
+        assert_eq!(extract_message(&stego, "one secret", false, None).unwrap_err(), Error::NoPayload);
+        let wrong = Seed::from_key("another secret").unwrap();
+        assert!(extract_message(&stego, "another secret", true, Some(wrong)).is_err());
+        let (_, message) = extract_message(&stego, "one secret", true, Some(seed)).unwrap();
+        assert_eq!(message.data, b"Hidden twice");
+    }
+
+    #[test]
+    fn extract_self_describing_aes() {
+        let path = temp_path("self_describing.png");
+        embed_message(&cover(32, 32), b"Top secret", "mysecretkey", &options(Algorithm::AesGcm, None), &path).unwrap();
+        let stego = image::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let (header, message) = extract_message(&stego, "mysecretkey", false, None).unwrap();
+        assert_eq!(header.algorithm, Algorithm::AesGcm);
+        assert_eq!(header.kdf, Kdf::Argon2id);
+        assert_eq!(message.data, b"Top secret");
+
+        let result = extract_message(&stego, "wrongkey", false, None);
+        assert!(matches!(result, Err(Error::BadKey(_))));
+    }
+
+    #[test]
+    fn extract_binary_file() {
+        let file = temp_path("secret.bin");
+        let data: Vec<u8> = (0..=255).rev().collect();
+        std::fs::write(&file, &data).unwrap();
+
+        let path = temp_path("binary_file.png");
+        embed_file(&cover(48, 48), &file, "pass", &options(Algorithm::ChaCha20Poly1305, Some(Seed::new("7"))), &path).unwrap();
+        let stego = image::open(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        std::fs::remove_file(&file).ok();
+
+        let (header, message) = extract_message(&stego, "pass", true, Some(Seed::new("7"))).unwrap();
+        assert!(header.is_file());
+        assert_eq!(message.data, data);
+        let name = std::path::Path::new(&file).file_name().unwrap().to_str().unwrap();
+        assert_eq!(message.file_name.unwrap(), name);
+    }
+
+    #[test]
+    fn extract_in_memory() {
+        let message = Message::new(b"no files touched".to_vec());
+        let stego = embed_to_image(&cover(24, 24), &message, "k", &options(Algorithm::Xor, None)).unwrap();
+
+        let mut buf = std::io::Cursor::new(Vec::new());
+        write_image(&stego, &mut buf, LosslessFormat::WebP).unwrap();
+        let reloaded = image::load_from_memory(buf.get_ref()).unwrap();
+
+        let (_, extracted) = extract_message(&reloaded, "k", false, None).unwrap();
+        assert_eq!(extracted, message);
+    }
+
+    #[test]
+    fn extract_no_payload() {
+        let result = extract_message(&cover(16, 16), "", false, None);
+        assert_eq!(result.unwrap_err(), Error::NoPayload);
+    }
+
+    #[test]
+    fn extract_corrupt_payload() {
+        let path = temp_path("corrupt.png");
+        embed_message(&cover(16, 16), b"Hello, World!", "", &options(Algorithm::None, None), &path).unwrap();
+        let mut stego = image::open(&path).unwrap().to_rgba8();
+        std::fs::remove_file(&path).ok();
+
+        // Header occupies the first 56 pixels; flip a payload bit after it
+        let px = stego.get_pixel_mut(4, 0);
+        px[0] ^= 1;
+        let result = extract_message(&DynamicImage::ImageRgba8(stego), "", false, None);
+        assert_eq!(result.unwrap_err(), Error::CorruptPayload("checksum mismatch".into()));
+    }
+
+    #[test]
+    fn extract_error_correction() {
+        let message = Message::new("error correction ".repeat(35).into_bytes());
+        let damage = |ecc: Ecc| {
+            let options = EmbedOptions { ecc, ..options(Algorithm::Xor, None) };
+            let mut stego = embed_to_image(&cover(48, 48), &message, "key", &options).unwrap().to_rgba8();
+            // One bit of the header, then every LSB of a 4x4 block of pixels
+            stego.get_pixel_mut(0, 3)[0] ^= 1;
+            for x in 20..24 {
+                for y in 10..14 {
+                    for c in 0..3 {
+                        stego.get_pixel_mut(x, y)[c] ^= 1;
+                    }
+                }
+            }
+            extract_message_corrected(&DynamicImage::ImageRgba8(stego), "key", false, None)
+        };
+
+        let (header, extracted, corrected) = damage(Ecc::High).unwrap();
+        assert_eq!(header.ecc, Ecc::High);
+        assert_eq!(extracted, message);
+        // The header byte and the bytes of four runs of twelve channels
+        assert!((9..=13).contains(&corrected), "{corrected}");
+
+        assert!(damage(Ecc::None).is_err());
+    }
 }
@@ -0,0 +1,381 @@
+use crate::Error;
+use crate::crypto::{Algorithm, kdf::{Kdf, key_check}};
+use super::channels::Channels;
+use super::compression::Compression;
+use super::ecc::{correct_header, header_parity, Ecc};
+
+/// Magic bytes that mark the start of an embedded payload
+pub const MAGIC: [u8; 4] = *b"CIMG";
+
+/// Current container format version. Version 1 had a 16-byte header without
+/// KDF and key fingerprint; version 2 stored the output of some algorithms
+/// base64-encoded, see [`Header::armored`]; version 3 stores raw ciphertext.
+pub const VERSION: u8 = 3;
+
+/// Oldest version [`Header::from_bytes`] reads; version 1 headers are laid out differently
+pub const MIN_VERSION: u8 = 2;
+
+/// Size of the serialized header in bytes
+pub const HEADER_LEN: usize = 21;
+
+/// Payload positions were shuffled with a seeded PRNG
+pub const FLAG_PRNG: u8 = 0b0000_0001;
+
+/// Plaintext starts with file metadata, see [`super::message::Message::encode`]
+pub const FLAG_FILE: u8 = 0b0000_0010;
+
+/// Flag bits holding the embedding depth minus one, see [`Header::depth`]
+pub const DEPTH_MASK: u8 = 0b0000_1100;
+const DEPTH_SHIFT: u8 = 2;
+
+/// Deepest supported embedding: the four low bits of every channel
+pub const MAX_DEPTH: u8 = 4;
+
+/// PRNG order is a [`super::permutation::Feistel`] permutation rather than the
+/// full shuffle older images used
+pub const FLAG_FEISTEL: u8 = 0b1000_0000;
+
+/// Bits of the codec byte holding the [`Compression::id`]; the rest hold the [`Ecc::id`]
+const COMPRESSION_MASK: u8 = 0b0000_1111;
+const ECC_SHIFT: u8 = 4;
+
+/// Flag bits holding the [`Channels::id`] the image was embedded in
+pub const CHANNELS_MASK: u8 = 0b0111_0000;
+const CHANNELS_SHIFT: u8 = 4;
+
+/// Container header written in front of every embedded payload.
+///
+/// Layout (big-endian):
+/// `magic[4] | version | algorithm | ecc << 4 | compression | flags | kdf | key_check: u32 | payload_len: u32 | crc32: u32`
+///
+/// With error correction the header is followed by [`super::ecc::HEADER_PARITY`] bytes of
+/// parity, `payload_len` counts the encoded payload and the checksum covers the decoded one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    /// Applied to the plaintext before encryption
+    pub compression: Compression,
+    /// Applied to the ciphertext before embedding
+    pub ecc: Ecc,
+    pub flags: u8,
+    pub kdf: Kdf,
+    /// Fingerprint of the embedding key, zero for keyless and KDF-based algorithms
+    pub key_check: u32,
+    pub payload_len: u32,
+    pub checksum: u32,
+}
+
+impl Header {
+    /// Build a header describing `payload`, encrypted with `algorithm` under `key`
+    /// (stretched with `kdf` if the algorithm uses one)
+    pub fn new(payload: &[u8], algorithm: Algorithm, kdf: Kdf, key: &str, use_prng: bool) -> Self {
+        Header {
+            version: VERSION,
+            algorithm,
+            compression: Compression::None,
+            ecc: Ecc::None,
+            flags: if use_prng { FLAG_PRNG | FLAG_FEISTEL } else { 0 },
+            kdf: if algorithm.uses_kdf() { kdf } else { Kdf::None },
+            key_check: if has_key_check(algorithm) { key_check(key) } else { 0 },
+            payload_len: payload.len() as u32,
+            checksum: crc32fast::hash(payload),
+        }
+    }
+
+    pub fn uses_prng(&self) -> bool {
+        self.flags & FLAG_PRNG != 0
+    }
+
+    pub fn uses_feistel(&self) -> bool {
+        self.flags & FLAG_FEISTEL != 0
+    }
+
+    /// Whether the payload is base64 text around the ciphertext, as version 2
+    /// containers wrote it for [`Algorithm::legacy_armored`] algorithms
+    pub fn armored(&self) -> bool {
+        self.version < 3 && self.algorithm.legacy_armored()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.flags & FLAG_FILE != 0
+    }
+
+    /// Low bits per channel carrying the payload (1–4). The header itself
+    /// always uses one bit per channel so it can be read before this is known.
+    pub fn depth(&self) -> u8 {
+        ((self.flags & DEPTH_MASK) >> DEPTH_SHIFT) + 1
+    }
+
+    pub fn set_depth(&mut self, depth: u8) -> Result<(), Error> {
+        if !(1..=MAX_DEPTH).contains(&depth) {
+            return Err(Error::InvalidArgument(format!(
+                "Bits per channel must be between 1 and {MAX_DEPTH}, got {depth}"
+            )));
+        }
+        self.flags = (self.flags & !DEPTH_MASK) | ((depth - 1) << DEPTH_SHIFT);
+        Ok(())
+    }
+
+    /// Channels carrying the header and payload; validated by [`Header::from_bytes`]
+    pub fn channels(&self) -> Channels {
+        Channels::from_id((self.flags & CHANNELS_MASK) >> CHANNELS_SHIFT).unwrap_or(Channels::Rgba)
+    }
+
+    pub fn set_channels(&mut self, channels: Channels) {
+        self.flags = (self.flags & !CHANNELS_MASK) | (channels.id() << CHANNELS_SHIFT);
+    }
+
+    /// Protect the payload with `ecc`: `payload_len` becomes the length of the
+    /// encoded payload, the checksum keeps covering the data itself
+    pub fn set_ecc(&mut self, ecc: Ecc) {
+        let data_len = self.ecc.decoded_len(self.payload_len as usize).unwrap_or_default();
+        self.ecc = ecc;
+        self.payload_len = ecc.encoded_len(data_len) as u32;
+    }
+
+    /// Bytes embedded in front of the payload: the header, then its parity if
+    /// the payload uses error correction
+    pub fn to_embedded_bytes(&self) -> Vec<u8> {
+        let bytes = self.to_bytes();
+        match self.ecc {
+            Ecc::None => bytes.to_vec(),
+            _ => [&bytes[..], &header_parity(&bytes)].concat(),
+        }
+    }
+
+    /// Parse a header and, if one could be read after it, the parity error correction
+    /// writes there, repairing the header with it when it says the payload is protected.
+    /// Returns the header and the number of bytes repaired.
+    pub fn from_embedded_bytes(bytes: &[u8], parity: Option<&[u8]>) -> Result<(Self, usize), Error> {
+        if let Some(parity) = parity
+            && let Some((fixed, corrected)) = correct_header(&bytes[..HEADER_LEN.min(bytes.len())], parity)
+            && let Ok(header) = Header::from_bytes(&fixed)
+            && header.ecc != Ecc::None
+        {
+            return Ok((header, corrected));
+        }
+        let header = Header::from_bytes(bytes)?;
+        if header.ecc != Ecc::None {
+            return Err(Error::CorruptHeader("header is damaged beyond what its parity can repair".into()));
+        }
+        Ok((header, 0))
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0u8; HEADER_LEN];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4] = self.version;
+        out[5] = self.algorithm.id();
+        out[6] = (self.ecc.id() << ECC_SHIFT) | self.compression.id();
+        out[7] = self.flags;
+        out[8] = self.kdf.id();
+        out[9..13].copy_from_slice(&self.key_check.to_be_bytes());
+        out[13..17].copy_from_slice(&self.payload_len.to_be_bytes());
+        out[17..21].copy_from_slice(&self.checksum.to_be_bytes());
+        out
+    }
+
+    /// Parse a header, rejecting anything that does not start with [`MAGIC`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return Err(Error::NoPayload);
+        }
+        let version = bytes[4];
+        if version == 1 {
+            return Err(Error::UnsupportedFormat("Unsupported container version: 1 (16-byte header)".into()));
+        }
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(Error::UnsupportedFormat(format!("Unsupported container version: {version}")));
+        }
+        let algorithm = Algorithm::from_id(bytes[5])
+            .ok_or_else(|| Error::CorruptHeader(format!("unknown algorithm id {}", bytes[5])))?;
+        let compression = Compression::from_id(bytes[6] & COMPRESSION_MASK)
+            .ok_or_else(|| Error::CorruptHeader(format!("unknown compression id {}", bytes[6] & COMPRESSION_MASK)))?;
+        let ecc = Ecc::from_id(bytes[6] >> ECC_SHIFT)
+            .ok_or_else(|| Error::CorruptHeader(format!("unknown error correction id {}", bytes[6] >> ECC_SHIFT)))?;
+        let payload_len = u32::from_be_bytes(bytes[13..17].try_into().unwrap());
+        if ecc.decoded_len(payload_len as usize).is_none() {
+            return Err(Error::CorruptHeader(format!("{payload_len} bytes is not a valid {ecc:?} error-corrected length")));
+        }
+
+        let kdf = Kdf::from_id(bytes[8])
+            .ok_or_else(|| Error::CorruptHeader(format!("unknown KDF id {}", bytes[8])))?;
+        let channels = (bytes[7] & CHANNELS_MASK) >> CHANNELS_SHIFT;
+        if Channels::from_id(channels).is_none() {
+            return Err(Error::CorruptHeader(format!("unknown channel selection {channels}")));
+        }
+
+        Ok(Header {
+            version,
+            algorithm,
+            compression,
+            ecc,
+            flags: bytes[7],
+            kdf,
+            key_check: u32::from_be_bytes(bytes[9..13].try_into().unwrap()),
+            payload_len,
+            checksum: u32::from_be_bytes(bytes[17..21].try_into().unwrap()),
+        })
+    }
+
+    /// Check that `key` is the one the payload was embedded with
+    pub fn verify_key(&self, key: &str) -> Result<(), Error> {
+        if has_key_check(self.algorithm) && key_check(key) != self.key_check {
+            return Err(Error::BadKey(format!(
+                "Wrong key: the supplied key does not match the one used to embed this image ({:?})",
+                self.algorithm
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that `payload`, after error correction, matches the stored checksum
+    pub fn verify(&self, payload: &[u8]) -> Result<(), Error> {
+        if crc32fast::hash(payload) != self.checksum {
+            return Err(Error::CorruptPayload("checksum mismatch".into()));
+        }
+        Ok(())
+    }
+}
+
+/// Salted KDFs are verified by decryption itself, see [`key_check`]
+fn has_key_check(algorithm: Algorithm) -> bool {
+    algorithm.requires_key() && !algorithm.uses_kdf()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stegano::ecc::HEADER_PARITY;
+
+    #[test]
+    fn header_roundtrip() {
+        let header = Header::new(b"Hello, World!", Algorithm::Aes, Kdf::Argon2id, "key", true);
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.uses_prng());
+        assert_eq!(parsed.payload_len, 13);
+        assert!(parsed.verify(b"Hello, World!").is_ok());
+    }
+
+    #[test]
+    fn header_records_kdf_and_key() {
+        let header = Header::new(b"cipher", Algorithm::Xor, Kdf::Argon2id, "mysecretkey", false);
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.kdf, Kdf::None);
+        assert!(parsed.verify_key("mysecretkey").is_ok());
+        assert!(matches!(parsed.verify_key("wrongkey"), Err(Error::BadKey(_))));
+
+        let header = Header::new(b"cipher", Algorithm::AesGcm, Kdf::Scrypt, "mysecretkey", false);
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.kdf, Kdf::Scrypt);
+        assert_eq!(parsed.key_check, 0);
+    }
+
+    #[test]
+    fn header_depth() {
+        let mut header = Header::new(b"payload", Algorithm::None, Kdf::None, "", true);
+        assert_eq!(header.depth(), 1);
+        header.set_depth(3).unwrap();
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.depth(), 3);
+        assert!(parsed.uses_prng());
+        assert!(matches!(header.set_depth(0), Err(Error::InvalidArgument(_))));
+        assert!(matches!(header.set_depth(MAX_DEPTH + 1), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn header_channels() {
+        let mut header = Header::new(b"payload", Algorithm::None, Kdf::None, "", false);
+        assert_eq!(header.channels(), Channels::Rgba);
+        header.set_channels(Channels::Green);
+        header.set_depth(2).unwrap();
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.channels(), Channels::Green);
+        assert_eq!(parsed.depth(), 2);
+
+        let mut bytes = header.to_bytes();
+        bytes[7] |= CHANNELS_MASK;
+        assert!(matches!(Header::from_bytes(&bytes), Err(Error::CorruptHeader(_))));
+    }
+
+    #[test]
+    fn header_ecc() {
+        let mut header = Header::new(&[0; 300], Algorithm::None, Kdf::None, "", false);
+        header.compression = Compression::Brotli;
+        header.set_ecc(Ecc::Medium);
+        assert_eq!(header.payload_len, 300 + 2 * 16);
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!((parsed.ecc, parsed.compression), (Ecc::Medium, Compression::Brotli));
+
+        // A flipped bit in the length is repaired with the parity that follows the header
+        let embedded = header.to_embedded_bytes();
+        assert_eq!(embedded.len(), Ecc::Medium.header_len());
+        let mut damaged = embedded.clone();
+        damaged[15] ^= 0x08;
+        let (bytes, parity) = damaged.split_at(HEADER_LEN);
+        assert_eq!(Header::from_embedded_bytes(bytes, Some(parity)).unwrap(), (header, 1));
+
+        // Unprotected headers ignore whatever follows them
+        let plain = Header::new(b"payload", Algorithm::None, Kdf::None, "", false);
+        assert_eq!(Header::from_embedded_bytes(&plain.to_bytes(), Some(&embedded[..HEADER_PARITY])).unwrap(), (plain, 0));
+
+        // 260 bytes would be one codeword too many for its data
+        let mut bytes = header.to_bytes();
+        bytes[13..17].copy_from_slice(&260u32.to_be_bytes());
+        assert!(matches!(Header::from_bytes(&bytes), Err(Error::CorruptHeader(_))));
+    }
+
+    #[test]
+    fn header_compression() {
+        let mut header = Header::new(b"payload", Algorithm::None, Kdf::None, "", false);
+        header.compression = Compression::Zstd;
+        assert_eq!(Header::from_bytes(&header.to_bytes()).unwrap().compression, Compression::Zstd);
+
+        let mut bytes = header.to_bytes();
+        bytes[6] = 9;
+        assert_eq!(
+            Header::from_bytes(&bytes).unwrap_err(),
+            Error::CorruptHeader("unknown compression id 9".into())
+        );
+    }
+
+    #[test]
+    fn header_missing_magic() {
+        let result = Header::from_bytes(&[0u8; HEADER_LEN]);
+        assert_eq!(result.unwrap_err(), Error::NoPayload);
+    }
+
+    #[test]
+    fn header_future_version() {
+        let mut bytes = Header::new(b"", Algorithm::None, Kdf::None, "", false).to_bytes();
+        bytes[4] = VERSION + 1;
+        let result = Header::from_bytes(&bytes);
+        assert_eq!(
+            result.unwrap_err(),
+            Error::UnsupportedFormat(format!("Unsupported container version: {}", VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn header_version_1_rejected() {
+        // The 16-byte layout: magic | version | algorithm | compression | flags | payload_len | crc32
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = 1;
+        bytes[8..12].copy_from_slice(&7u32.to_be_bytes());
+        let result = Header::from_bytes(&bytes);
+        assert_eq!(
+            result.unwrap_err(),
+            Error::UnsupportedFormat("Unsupported container version: 1 (16-byte header)".into())
+        );
+    }
+
+    #[test]
+    fn header_checksum_mismatch() {
+        let header = Header::new(b"payload", Algorithm::None, Kdf::None, "", false);
+        assert!(header.verify(b"paylaod").is_err());
+    }
+}
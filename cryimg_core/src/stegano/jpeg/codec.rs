@@ -0,0 +1,618 @@
+use crate::Error;
+use super::huffman::{Decoder, HuffmanTable};
+
+/// Quantized DCT coefficients of a sequential Huffman-coded JPEG, with everything
+/// else needed to write it back. Only the entropy-coded data is re-encoded; the
+/// quantization tables, frame header and metadata segments are copied verbatim,
+/// so unchanged coefficients give back the same picture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jpeg {
+    /// Marker segments other than DHT, DRI and SOS, in file order: (marker, body)
+    segments: Vec<(u8, Vec<u8>)>,
+    pub width: u16,
+    pub height: u16,
+    pub components: Vec<Component>,
+}
+
+/// One colour component and its 8x8 blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    dc_table: u8,
+    ac_table: u8,
+    /// Blocks per row and column, padded out to whole MCUs
+    pub blocks_wide: usize,
+    pub blocks_high: usize,
+    /// Row-major blocks, coefficients in zigzag order (DC first)
+    pub blocks: Vec<[i16; 64]>,
+}
+
+/// Frames with more coefficient blocks than this, over all components, are rejected.
+/// At 128 bytes a block that is 1 GiB, so a crafted frame header cannot claim unbounded memory.
+pub const MAX_BLOCKS: usize = (1 << 30) / size_of::<[i16; 64]>();
+
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const DHT: u8 = 0xC4;
+const DRI: u8 = 0xDD;
+
+fn malformed(msg: impl std::fmt::Display) -> Error {
+    Error::Io(format!("Malformed JPEG: {msg}"))
+}
+
+impl Jpeg {
+    /// Read the coefficients of a baseline or extended sequential JPEG; progressive,
+    /// arithmetic-coded and 12-bit files are reported as unsupported
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        if !data.starts_with(&[0xFF, SOI]) {
+            return Err(malformed("missing start of image marker"));
+        }
+        let mut pos = 2;
+        let mut segments = Vec::new();
+        let mut frame: Option<(u16, u16, Vec<Component>)> = None;
+        let mut tables: [[Option<Decoder>; 4]; 2] = Default::default();
+        let mut restart_interval = 0usize;
+
+        loop {
+            let marker = next_marker(data, &mut pos)?;
+            match marker {
+                EOI => break,
+                0xD0..=0xD7 | 0x01 => continue,
+                _ => {}
+            }
+            let len = data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                .ok_or_else(|| malformed("truncated segment"))?;
+            let body = data.get(pos + 2..pos + len).filter(|_| len >= 2)
+                .ok_or_else(|| malformed("truncated segment"))?;
+            pos += len;
+
+            match marker {
+                0xC0 | 0xC1 => {
+                    if frame.is_some() {
+                        return Err(malformed("more than one frame"));
+                    }
+                    frame = Some(parse_frame(body, data.len() - pos)?);
+                    segments.push((marker, body.to_vec()));
+                }
+                0xC2 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                    return Err(Error::UnsupportedFormat(
+                        "Only baseline and sequential JPEGs can carry a payload, not progressive, lossless or arithmetic-coded ones".into(),
+                    ));
+                }
+                DHT => parse_tables(body, &mut tables)?,
+                DRI => {
+                    let interval = body.get(..2).ok_or_else(|| malformed("short DRI segment"))?;
+                    restart_interval = u16::from_be_bytes([interval[0], interval[1]]) as usize;
+                }
+                SOS => {
+                    let Some((width, height, mut components)) = frame.take() else {
+                        return Err(malformed("scan before frame header"));
+                    };
+                    parse_scan(body, &mut components)?;
+                    pos = decode_scan(data, pos, &mut components, &tables, restart_interval)?;
+                    // Anything after the one scan (bar EOI) would be a second scan
+                    if next_marker(data, &mut pos)? != EOI {
+                        return Err(Error::UnsupportedFormat(
+                            "JPEGs with more than one scan are not supported".into(),
+                        ));
+                    }
+                    return Ok(Jpeg { segments, width, height, components });
+                }
+                _ => segments.push((marker, body.to_vec())),
+            }
+        }
+        Err(malformed("no image data"))
+    }
+
+    /// Write the JPEG back with freshly optimized Huffman tables and no restart markers
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0xFF, SOI];
+        for (marker, body) in &self.segments {
+            push_segment(&mut out, *marker, body);
+        }
+
+        // Symbol statistics for every table the scan uses, then the tables themselves
+        let mut freq = [[[0u32; 256]; 4]; 2];
+        self.scan(|component, block, predictor| {
+            let (dc, ac) = freq.split_at_mut(1);
+            count_block(block, predictor, &mut dc[0][component.dc_table as usize], &mut ac[0][component.ac_table as usize]);
+        });
+        let mut codes = [[[(0u16, 0u8); 256]; 4]; 2];
+        let mut dht = Vec::new();
+        for class in 0..2 {
+            for id in 0..4 {
+                if freq[class][id].iter().any(|&f| f > 0) {
+                    let table = HuffmanTable::optimal(&freq[class][id]);
+                    codes[class][id] = table.codes();
+                    dht.extend(table.to_bytes(class as u8, id as u8));
+                }
+            }
+        }
+        push_segment(&mut out, DHT, &dht);
+
+        let mut sos = vec![self.components.len() as u8];
+        for component in &self.components {
+            sos.extend([component.id, (component.dc_table << 4) | component.ac_table]);
+        }
+        sos.extend([0, 63, 0]);
+        push_segment(&mut out, SOS, &sos);
+
+        let mut writer = BitWriter::default();
+        self.scan(|component, block, predictor| {
+            let dc = &codes[0][component.dc_table as usize];
+            let ac = &codes[1][component.ac_table as usize];
+            encode_block(&mut writer, block, predictor, dc, ac);
+        });
+        out.extend(writer.finish());
+        out.extend([0xFF, EOI]);
+        out
+    }
+
+    /// Visit every block in scan order with the DC prediction it is coded against
+    fn scan(&self, mut visit: impl FnMut(&Component, &[i16; 64], i16)) {
+        let mut predictors = vec![0i16; self.components.len()];
+        for (c, index) in scan_order(&self.components) {
+            let component = &self.components[c];
+            let block = &component.blocks[index];
+            visit(component, block, predictors[c]);
+            predictors[c] = block[0];
+        }
+    }
+}
+
+/// (component, block index) of each block in the order the scan codes them:
+/// MCU by MCU when interleaved, row by row for a single component
+fn scan_order(components: &[Component]) -> Vec<(usize, usize)> {
+    if let [component] = components {
+        return (0..component.blocks.len()).map(|i| (0, i)).collect();
+    }
+    let first = &components[0];
+    let (mcus_wide, mcus_high) = (first.blocks_wide / first.h, first.blocks_high / first.v);
+    let mut order = Vec::new();
+    for my in 0..mcus_high {
+        for mx in 0..mcus_wide {
+            for (c, component) in components.iter().enumerate() {
+                for v in 0..component.v {
+                    for h in 0..component.h {
+                        let (row, col) = (my * component.v + v, mx * component.h + h);
+                        order.push((c, row * component.blocks_wide + col));
+                    }
+                }
+            }
+        }
+    }
+    order
+}
+
+/// Skip to the next marker and return it, leaving `pos` after it
+fn next_marker(data: &[u8], pos: &mut usize) -> Result<u8, Error> {
+    while *pos < data.len() && data[*pos] != 0xFF {
+        *pos += 1;
+    }
+    while *pos < data.len() && data[*pos] == 0xFF {
+        *pos += 1;
+    }
+    let marker = *data.get(*pos).ok_or_else(|| malformed("missing end of image marker"))?;
+    *pos += 1;
+    Ok(marker)
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend([0xFF, marker]);
+    out.extend(((body.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+/// Parse a frame header. `remaining` is the length of the file after it, which must
+/// hold at least two bits per block: a DC and an end-of-block code.
+fn parse_frame(body: &[u8], remaining: usize) -> Result<(u16, u16, Vec<Component>), Error> {
+    let [precision, h1, h0, w1, w0, count, ref rest @ ..] = *body else {
+        return Err(malformed("short frame header"));
+    };
+    if precision != 8 {
+        return Err(Error::UnsupportedFormat(format!("{precision}-bit JPEGs are not supported")));
+    }
+    let (height, width) = (u16::from_be_bytes([h1, h0]), u16::from_be_bytes([w1, w0]));
+    if width == 0 || height == 0 {
+        return Err(Error::UnsupportedFormat("JPEGs without a height in the frame header are not supported".into()));
+    }
+    if count == 0 || rest.len() < count as usize * 3 {
+        return Err(malformed("short frame header"));
+    }
+
+    let sampling: Vec<(u8, usize, usize)> = rest.chunks(3).take(count as usize)
+        .map(|c| (c[0], (c[1] >> 4) as usize, (c[1] & 15) as usize))
+        .collect();
+    if sampling.iter().any(|&(_, h, v)| !(1..=4).contains(&h) || !(1..=4).contains(&v)) {
+        return Err(malformed("invalid sampling factors"));
+    }
+    let h_max = sampling.iter().map(|s| s.1).max().unwrap();
+    let v_max = sampling.iter().map(|s| s.2).max().unwrap();
+    let mcus_wide = (width as usize).div_ceil(8 * h_max);
+    let mcus_high = (height as usize).div_ceil(8 * v_max);
+
+    let dimensions: Vec<(usize, usize)> = sampling.iter().map(|&(_, h, v)| {
+        // A lone component is coded without padding to whole MCUs
+        if count == 1 {
+            ((width as usize * h).div_ceil(8 * h_max), (height as usize * v).div_ceil(8 * v_max))
+        } else {
+            (mcus_wide * h, mcus_high * v)
+        }
+    }).collect();
+    let blocks: usize = dimensions.iter().map(|(wide, high)| wide * high).sum();
+    if blocks > MAX_BLOCKS {
+        return Err(Error::UnsupportedFormat(format!(
+            "JPEG frame of {width}x{height} with {count} components is too large (over {MAX_BLOCKS} blocks)"
+        )));
+    }
+    if blocks > remaining.saturating_mul(4) {
+        return Err(malformed(format!("frame header claims {blocks} blocks, more than the file can hold")));
+    }
+
+    let components = sampling.iter().zip(dimensions).map(|(&(id, h, v), (blocks_wide, blocks_high))| {
+        Component {
+            id, h, v,
+            dc_table: 0,
+            ac_table: 0,
+            blocks_wide,
+            blocks_high,
+            blocks: vec![[0; 64]; blocks_wide * blocks_high],
+        }
+    }).collect();
+    Ok((width, height, components))
+}
+
+fn parse_tables(mut body: &[u8], tables: &mut [[Option<Decoder>; 4]; 2]) -> Result<(), Error> {
+    while let [class_id, ref rest @ ..] = *body {
+        let (class, id) = ((class_id >> 4) as usize, (class_id & 15) as usize);
+        if class > 1 || id > 3 || rest.len() < 16 {
+            return Err(malformed("invalid Huffman table"));
+        }
+        let counts: [u8; 16] = rest[..16].try_into().unwrap();
+        let total: usize = counts.iter().map(|&c| c as usize).sum();
+        let symbols = rest.get(16..16 + total).ok_or_else(|| malformed("truncated Huffman table"))?;
+        tables[class][id] = Some(Decoder::new(&HuffmanTable { counts, symbols: symbols.to_vec() }));
+        body = &rest[16 + total..];
+    }
+    Ok(())
+}
+
+fn parse_scan(body: &[u8], components: &mut [Component]) -> Result<(), Error> {
+    let count = *body.first().ok_or_else(|| malformed("short scan header"))? as usize;
+    if count != components.len() {
+        return Err(Error::UnsupportedFormat(
+            "JPEGs whose scan does not cover every component are not supported".into(),
+        ));
+    }
+    let selectors = body.get(1..1 + 2 * count).ok_or_else(|| malformed("short scan header"))?;
+    for (component, selector) in components.iter_mut().zip(selectors.chunks(2)) {
+        if selector[0] != component.id {
+            return Err(Error::UnsupportedFormat("JPEG scan lists components out of order".into()));
+        }
+        (component.dc_table, component.ac_table) = (selector[1] >> 4, selector[1] & 15);
+        if component.dc_table > 3 || component.ac_table > 3 {
+            return Err(malformed("invalid Huffman table selector"));
+        }
+    }
+    Ok(())
+}
+
+/// Decode the entropy-coded data starting at `pos`; returns where it ends
+fn decode_scan(
+    data: &[u8],
+    pos: usize,
+    components: &mut [Component],
+    tables: &[[Option<Decoder>; 4]; 2],
+    restart_interval: usize,
+) -> Result<usize, Error> {
+    let mut reader = BitReader { data, pos, bits: 0, count: 0 };
+    let mut predictors = vec![0i16; components.len()];
+    let order = scan_order(components);
+    let blocks_per_mcu = if components.len() == 1 { 1 } else { components.iter().map(|c| c.h * c.v).sum() };
+
+    for (n, &(c, index)) in order.iter().enumerate() {
+        let mcu = n / blocks_per_mcu;
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 && n % blocks_per_mcu == 0 {
+            reader.restart()?;
+            predictors.fill(0);
+        }
+        let component = &mut components[c];
+        let missing = || malformed("scan uses an undefined Huffman table");
+        let dc = tables[0][component.dc_table as usize].as_ref().ok_or_else(missing)?;
+        let ac = tables[1][component.ac_table as usize].as_ref().ok_or_else(missing)?;
+        decode_block(&mut reader, &mut component.blocks[index], &mut predictors[c], dc, ac)?;
+    }
+    Ok(reader.pos)
+}
+
+fn decode_block(
+    reader: &mut BitReader,
+    block: &mut [i16; 64],
+    predictor: &mut i16,
+    dc: &Decoder,
+    ac: &Decoder,
+) -> Result<(), Error> {
+    let truncated = || malformed("truncated or corrupt scan data");
+    let size = dc.decode(|| reader.bit()).ok_or_else(truncated)?;
+    if size > 11 {
+        return Err(truncated());
+    }
+    *predictor = predictor.wrapping_add(reader.value(size).ok_or_else(truncated)?);
+    block[0] = *predictor;
+
+    let mut k = 1;
+    while k < 64 {
+        let symbol = ac.decode(|| reader.bit()).ok_or_else(truncated)?;
+        let (run, size) = ((symbol >> 4) as usize, symbol & 15);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 || size > 10 {
+            return Err(truncated());
+        }
+        block[k] = reader.value(size).ok_or_else(truncated)?;
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Magnitude category of a coefficient: the number of bits in its absolute value
+fn category(value: i16) -> u8 {
+    (16 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+fn count_block(block: &[i16; 64], predictor: i16, dc: &mut [u32; 256], ac: &mut [u32; 256]) {
+    dc[category(block[0].wrapping_sub(predictor)) as usize] += 1;
+    let mut run = 0;
+    for &coefficient in &block[1..] {
+        if coefficient == 0 {
+            run += 1;
+            continue;
+        }
+        while run >= 16 {
+            ac[0xF0] += 1;
+            run -= 16;
+        }
+        ac[(run << 4) | category(coefficient) as usize] += 1;
+        run = 0;
+    }
+    if run > 0 {
+        ac[0x00] += 1;
+    }
+}
+
+fn encode_block(
+    writer: &mut BitWriter,
+    block: &[i16; 64],
+    predictor: i16,
+    dc: &[(u16, u8); 256],
+    ac: &[(u16, u8); 256],
+) {
+    let diff = block[0].wrapping_sub(predictor);
+    let size = category(diff);
+    writer.code(dc[size as usize]);
+    writer.value(diff, size);
+
+    let mut run = 0;
+    for &coefficient in &block[1..] {
+        if coefficient == 0 {
+            run += 1;
+            continue;
+        }
+        while run >= 16 {
+            writer.code(ac[0xF0]);
+            run -= 16;
+        }
+        let size = category(coefficient);
+        writer.code(ac[(run << 4) | size as usize]);
+        writer.value(coefficient, size);
+        run = 0;
+    }
+    if run > 0 {
+        writer.code(ac[0x00]);
+    }
+}
+
+/// Entropy-coded bits, with stuffed zero bytes removed; stops at the next marker
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u8,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Option<bool> {
+        if self.count == 0 {
+            let byte = *self.data.get(self.pos)?;
+            if byte == 0xFF {
+                // 0xFF 0x00 is a literal 0xFF; anything else is a marker
+                if *self.data.get(self.pos + 1)? != 0 {
+                    return None;
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.bits = byte as u32;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Some((self.bits >> self.count) & 1 == 1)
+    }
+
+    /// A `size`-bit value, with the JPEG convention for negative numbers
+    fn value(&mut self, size: u8) -> Option<i16> {
+        let mut value = 0i32;
+        for _ in 0..size {
+            value = (value << 1) | self.bit()? as i32;
+        }
+        if size > 0 && value < 1 << (size - 1) {
+            value -= (1 << size) - 1;
+        }
+        Some(value as i16)
+    }
+
+    /// Drop the remaining bits of the byte and step over an RSTn marker
+    fn restart(&mut self) -> Result<(), Error> {
+        self.count = 0;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(malformed("missing restart marker")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn code(&mut self, (code, len): (u16, u8)) {
+        self.push(code as u32, len);
+    }
+
+    fn value(&mut self, value: i16, size: u8) {
+        let bits = if value < 0 { value as i32 - 1 } else { value as i32 };
+        self.push(bits as u32 & ((1 << size) - 1), size);
+    }
+
+    fn push(&mut self, bits: u32, len: u8) {
+        self.bits = (self.bits << len) | bits;
+        self.count += len;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.bits >> self.count) as u8;
+            self.out.push(byte);
+            if byte == 0xFF {
+                self.out.push(0);
+            }
+        }
+        self.bits &= (1 << self.count) - 1;
+    }
+
+    /// Pad the last byte with ones
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            let pad = 8 - self.count;
+            self.push((1 << pad) - 1, pad);
+        }
+        self.out
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::jpeg::JpegEncoder, DynamicImage, RgbImage};
+
+    fn jpeg_cover(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 5 + y * 3) as u8, (x * y / 3) as u8, (x ^ y) as u8 * 4])
+        });
+        let mut out = Vec::new();
+        JpegEncoder::new_with_quality(&mut out, 85).encode_image(&DynamicImage::ImageRgb8(img)).unwrap();
+        out
+    }
+
+    #[test]
+    fn codec_roundtrip_is_lossless() {
+        for (width, height) in [(8, 8), (37, 21), (64, 48)] {
+            let original = jpeg_cover(width, height);
+            let jpeg = Jpeg::decode(&original).unwrap();
+            assert_eq!((jpeg.width, jpeg.height), (width as u16, height as u16));
+
+            // Same coefficients after re-encoding, and the same pixels
+            let encoded = jpeg.encode();
+            assert_eq!(Jpeg::decode(&encoded).unwrap(), jpeg);
+            let before = image::load_from_memory(&original).unwrap().to_rgb8();
+            let after = image::load_from_memory(&encoded).unwrap().to_rgb8();
+            assert_eq!(before, after);
+        }
+    }
+
+    #[test]
+    fn codec_grayscale_and_restarts() {
+        let gray = DynamicImage::ImageRgb8(RgbImage::from_fn(50, 30, |x, y| image::Rgb([(x * 4 + y) as u8; 3]))).to_luma8();
+        let mut original = Vec::new();
+        JpegEncoder::new_with_quality(&mut original, 90).encode_image(&gray).unwrap();
+        let jpeg = Jpeg::decode(&original).unwrap();
+        assert_eq!(jpeg.components.len(), 1);
+        assert_eq!(jpeg.components[0].blocks.len(), 7 * 4);
+
+        // The same blocks coded with a restart marker after every MCU, which also
+        // restarts DC prediction, decode to the same coefficients
+        let component = &jpeg.components[0];
+        let mut freq = [[0u32; 256]; 2];
+        for block in &component.blocks {
+            let (dc, ac) = freq.split_at_mut(1);
+            count_block(block, 0, &mut dc[0], &mut ac[0]);
+        }
+        let (dc, ac) = (HuffmanTable::optimal(&freq[0]), HuffmanTable::optimal(&freq[1]));
+        let mut restarts = vec![0xFF, SOI];
+        for (marker, body) in &jpeg.segments {
+            push_segment(&mut restarts, *marker, body);
+        }
+        push_segment(&mut restarts, DRI, &[0, 1]);
+        push_segment(&mut restarts, DHT, &[dc.to_bytes(0, component.dc_table), ac.to_bytes(1, component.ac_table)].concat());
+        push_segment(&mut restarts, SOS, &[1, component.id, (component.dc_table << 4) | component.ac_table, 0, 63, 0]);
+        for (i, block) in component.blocks.iter().enumerate() {
+            if i > 0 {
+                restarts.extend([0xFF, 0xD0 + (i as u8 - 1) % 8]);
+            }
+            let mut writer = BitWriter::default();
+            encode_block(&mut writer, block, 0, &dc.codes(), &ac.codes());
+            restarts.extend(writer.finish());
+        }
+        restarts.extend([0xFF, EOI]);
+        assert_eq!(Jpeg::decode(&restarts).unwrap(), jpeg);
+        assert_eq!(image::load_from_memory(&restarts).unwrap().to_luma8(), image::load_from_memory(&original).unwrap().to_luma8());
+    }
+
+    #[test]
+    fn codec_rejects_unsupported() {
+        assert!(matches!(Jpeg::decode(b"\x89PNG\r\n"), Err(Error::Io(_))));
+        let mut progressive = jpeg_cover(16, 16);
+        let sof = progressive.windows(2).position(|w| w == [0xFF, 0xC0]).unwrap();
+        progressive[sof + 1] = 0xC2;
+        assert!(matches!(Jpeg::decode(&progressive), Err(Error::UnsupportedFormat(_))));
+
+        let truncated = jpeg_cover(32, 32);
+        assert!(Jpeg::decode(&truncated[..truncated.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn codec_rejects_oversized_frames() {
+        // A frame header alone, claiming three components at the largest size
+        let frame = |size: u16, components: u8| {
+            let mut data = vec![0xFF, SOI];
+            let mut body = vec![8];
+            body.extend(size.to_be_bytes());
+            body.extend(size.to_be_bytes());
+            body.push(components);
+            for id in 1..=components {
+                body.extend([id, 0x11, 0]);
+            }
+            push_segment(&mut data, 0xC0, &body);
+            data.extend([0xFF, EOI]);
+            data
+        };
+        assert!(matches!(Jpeg::decode(&frame(u16::MAX, 3)), Err(Error::UnsupportedFormat(_))));
+        // Within the limit, but far more blocks than the few bytes that follow could code
+        assert!(matches!(Jpeg::decode(&frame(4096, 1)), Err(Error::Io(_))));
+    }
+}
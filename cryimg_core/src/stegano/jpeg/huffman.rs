@@ -0,0 +1,180 @@
+/// A Huffman table as a DHT segment stores it: how many codes there are of each
+/// length from 1 to 16 bits, then the symbols in order of increasing code length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HuffmanTable {
+    pub counts: [u8; 16],
+    pub symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+    /// The shortest table for symbols seen `freq` times, with no code longer than
+    /// 16 bits and none made of all ones (ITU T.81, Annex K.2)
+    pub fn optimal(freq: &[u32; 256]) -> Self {
+        // Symbol 256 reserves the all-ones code and is dropped again below
+        let mut freq: Vec<u64> = freq.iter().map(|&f| f as u64).chain([1]).collect();
+        let mut code_size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+
+        // Merge the two least frequent subtrees until one is left
+        loop {
+            let least = |skip: Option<usize>| {
+                (0..257)
+                    .filter(|&v| freq[v] > 0 && Some(v) != skip)
+                    .min_by_key(|&v| (freq[v], std::cmp::Reverse(v)))
+            };
+            let Some(v1) = least(None) else { break };
+            let Some(v2) = least(Some(v1)) else { break };
+
+            freq[v1] += freq[v2];
+            freq[v2] = 0;
+            for mut v in [v1, v2] {
+                code_size[v] += 1;
+                while others[v] != usize::MAX {
+                    v = others[v];
+                    code_size[v] += 1;
+                }
+            }
+            let mut tail = v1;
+            while others[tail] != usize::MAX {
+                tail = others[tail];
+            }
+            others[tail] = v2;
+        }
+
+        let mut bits = [0usize; 33];
+        for &size in &code_size {
+            if size > 0 {
+                bits[size.min(32)] += 1;
+            }
+        }
+        // Move codes longer than 16 bits up the tree
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // Drop the reserved code, which is the longest
+        if let Some(longest) = (1..=16).rev().find(|&i| bits[i] > 0) {
+            bits[longest] -= 1;
+        }
+
+        let mut symbols: Vec<u8> = (0..256).filter(|&v| code_size[v] > 0).map(|v| v as u8).collect();
+        symbols.sort_by_key(|&v| code_size[v as usize]);
+        HuffmanTable {
+            counts: std::array::from_fn(|i| bits[i + 1] as u8),
+            symbols,
+        }
+    }
+
+    /// Canonical (code, length) of each symbol; length 0 for symbols without a code
+    pub fn codes(&self) -> [(u16, u8); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut symbols = self.symbols.iter();
+        for (len, &count) in (1..=16).zip(&self.counts) {
+            for _ in 0..count {
+                if let Some(&symbol) = symbols.next() {
+                    codes[symbol as usize] = (code, len);
+                }
+                code = code.wrapping_add(1);
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    /// Serialized body of a DHT entry for this table
+    pub fn to_bytes(&self, class: u8, id: u8) -> Vec<u8> {
+        let mut out = vec![(class << 4) | id];
+        out.extend_from_slice(&self.counts);
+        out.extend_from_slice(&self.symbols);
+        out
+    }
+}
+
+/// Looks symbols up code by code (ITU T.81, F.2.2.3)
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    symbols: Vec<u8>,
+    /// Smallest and largest code of each length, and the index of the first
+    /// symbol with that length; `None` for lengths without codes
+    lengths: [Option<(u32, u32, usize)>; 16],
+}
+
+impl Decoder {
+    pub fn new(table: &HuffmanTable) -> Self {
+        let mut lengths = [None; 16];
+        let (mut code, mut index) = (0u32, 0usize);
+        for (len, &count) in table.counts.iter().enumerate() {
+            if count > 0 {
+                lengths[len] = Some((code, code + count as u32 - 1, index));
+            }
+            code = (code + count as u32) << 1;
+            index += count as usize;
+        }
+        Decoder { symbols: table.symbols.clone(), lengths }
+    }
+
+    /// Decode one symbol, reading bits from `next_bit`; `None` for a code not in the table
+    pub fn decode(&self, mut next_bit: impl FnMut() -> Option<bool>) -> Option<u8> {
+        let mut code = 0u32;
+        for length in &self.lengths {
+            code = (code << 1) | next_bit()? as u32;
+            if let Some((min, max, index)) = *length
+                && code <= max
+            {
+                return self.symbols.get(index + (code - min) as usize).copied();
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huffman_optimal_codes_decode() {
+        let mut freq = [0u32; 256];
+        for (symbol, count) in [(0x00, 500), (0x01, 300), (0x11, 120), (0xF0, 7), (0x3A, 1), (0x2B, 1)] {
+            freq[symbol] = count;
+        }
+        let table = HuffmanTable::optimal(&freq);
+        assert_eq!(table.symbols.len(), 6);
+        let codes = table.codes();
+        // More frequent symbols never get longer codes
+        assert!(codes[0x00].1 <= codes[0x01].1 && codes[0x01].1 <= codes[0x11].1);
+
+        let decoder = Decoder::new(&table);
+        for symbol in [0x00, 0x01, 0x11, 0xF0, 0x3A, 0x2B] {
+            let (code, len) = codes[symbol];
+            let mut bits = (0..len).rev().map(|i| (code >> i) & 1 == 1);
+            assert_eq!(decoder.decode(|| bits.next()), Some(symbol as u8));
+            // No code is all ones
+            assert_ne!(code, (1 << len) - 1);
+        }
+    }
+
+    #[test]
+    fn huffman_limits_code_length() {
+        // Fibonacci frequencies make the deepest possible tree
+        let mut freq = [0u32; 256];
+        let (mut a, mut b) = (1u32, 1u32);
+        for f in freq.iter_mut().take(30) {
+            *f = a;
+            (a, b) = (b, a + b);
+        }
+        let table = HuffmanTable::optimal(&freq);
+        assert_eq!(table.symbols.len(), 30);
+        assert!(table.codes().iter().all(|&(_, len)| len <= 16));
+    }
+}
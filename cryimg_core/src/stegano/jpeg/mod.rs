@@ -0,0 +1,357 @@
+//! Embedding in the quantized DCT coefficients of a JPEG, so the stego image can
+//! stay a JPEG. Decoding a JPEG to pixels and saving it again would quantize away
+//! any LSB payload; here the coefficients are read and written back losslessly and
+//! only the payload's changes touch them.
+//!
+//! Bits go into the nonzero AC coefficients, F5-style: a coefficient carries the low
+//! bit of its magnitude, and changing it always moves the magnitude towards zero.
+//! When that makes it zero ("shrinkage") the extractor will skip it, so the same bits
+//! are embedded again further on. The payload uses matrix encoding: `k` bits go into
+//! `2^k - 1` coefficients with at most one of them changed.
+
+pub mod codec;
+mod huffman;
+
+pub use codec::Jpeg;
+
+use crate::Error;
+use crate::utils::Seed;
+use super::embed::{seal_message, EmbedOptions};
+use super::extract::{check_payload, open_payload};
+use super::ecc::{Ecc, HEADER_PARITY};
+use super::header::{Header, HEADER_LEN, MAX_DEPTH};
+use super::message::Message;
+use super::output::OutputFormat;
+use super::permutation::Permutation;
+use super::verify::{compare_images, verify_saved_jpeg, Verification};
+
+/// Whether `data` starts like a JPEG file
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8, 0xFF])
+}
+
+/// Encrypt `message` and embed it in the JPEG `cover`, returning the stego JPEG.
+/// The header records the matrix encoding in its depth field; channels, depth and
+/// strategy in `options` do not apply here.
+pub fn embed_jpeg(cover: &[u8], message: &Message, key: &str, options: &EmbedOptions) -> Result<Vec<u8>, Error> {
+    embed_coefficients(cover, message, key, options).map(|(_, _, stego)| stego)
+}
+
+/// [`embed_jpeg`], writing the stego JPEG to `out_path` and reading it back to check
+/// that it still carries the payload, see [`verify_saved_jpeg`], and how much it changed
+pub fn embed_jpeg_file(
+    cover: &[u8],
+    message: &Message,
+    key: &str,
+    options: &EmbedOptions,
+    out_path: &str,
+) -> Result<Verification, Error> {
+    if OutputFormat::from_path(out_path)? != OutputFormat::Jpeg {
+        return Err(Error::InvalidArgument(format!(
+            "Embedding into DCT coefficients writes a JPEG; give {out_path} a .jpg or .jpeg extension"
+        )));
+    }
+    let (header, payload, stego) = embed_coefficients(cover, message, key, options)?;
+    std::fs::write(out_path, stego).map_err(|e| Error::Io(format!("Failed to save image: {e}")))?;
+    let (header, stego) = verify_saved_jpeg(out_path, &header, &payload, options.seed)?;
+    let cover = image::load_from_memory(cover).map_err(|e| Error::Io(format!("Failed to decode the cover: {e}")))?;
+    Ok(Verification { header, metrics: compare_images(&cover, &stego)? })
+}
+
+/// The header, the payload behind it and the stego JPEG
+fn embed_coefficients(cover: &[u8], message: &Message, key: &str, options: &EmbedOptions) -> Result<(Header, Vec<u8>, Vec<u8>), Error> {
+    let mut jpeg = Jpeg::decode(cover)?;
+    let (mut header, payload) = seal_message(message, key, options)?;
+    let order = |len| match (options.use_prng, options.seed) {
+        (false, _) => Ok(Permutation::Identity),
+        (true, Some(seed)) => Ok(Permutation::feistel(len, &seed)),
+        (true, None) => Err(Error::MissingSeed),
+    };
+
+    // The most efficient matrix encoding the cover has room for
+    let coefficients = Coefficients::read(&jpeg);
+    for k in (1..=MAX_DEPTH).rev() {
+        header.set_depth(k)?;
+        let mut stego = Coefficients { order: order(coefficients.values.len())?, ..coefficients.clone() };
+        if stego.embed(&header.to_embedded_bytes(), &payload, k).is_some() {
+            stego.write(&mut jpeg);
+            return Ok((header, payload, jpeg.encode()));
+        }
+    }
+    Err(Error::CapacityExceeded {
+        needed: header.ecc.header_len() + payload.len(),
+        available: coefficients.capacity(),
+    })
+}
+
+/// Read just the container header of a stego JPEG
+pub fn read_jpeg_header(data: &[u8], use_prng: bool, seed: Option<Seed>) -> Result<Header, Error> {
+    Coefficients::locate(data, use_prng, seed).map(|(_, header, _)| header)
+}
+
+/// JPEG counterpart of [`super::extract::extract_payload`]
+pub fn extract_jpeg_payload(data: &[u8], use_prng: bool, seed: Option<Seed>) -> Result<(Header, Vec<u8>, usize), Error> {
+    let (mut coefficients, header, header_corrected) = Coefficients::locate(data, use_prng, seed)?;
+    let payload = coefficients
+        .extract(header.payload_len as usize, header.depth())
+        .ok_or_else(|| Error::CorruptHeader(format!(
+            "payload length ({} bytes) exceeds image capacity", header.payload_len
+        )))?;
+    let (payload, payload_corrected) = check_payload(&header, &payload)?;
+    Ok((header, payload, header_corrected + payload_corrected))
+}
+
+/// JPEG counterpart of [`super::extract::extract_message_corrected`]
+pub fn extract_jpeg(data: &[u8], key: &str, use_prng: bool, seed: Option<Seed>) -> Result<(Header, Message, usize), Error> {
+    let (header, payload, corrected) = extract_jpeg_payload(data, use_prng, seed)?;
+    Ok((header, open_payload(&header, payload, key)?, corrected))
+}
+
+/// Rough number of container bytes (header included) a JPEG can carry without
+/// matrix encoding, allowing for the coefficients lost to shrinkage
+pub fn jpeg_capacity(data: &[u8]) -> Result<usize, Error> {
+    Ok(Coefficients::read(&Jpeg::decode(data)?).capacity())
+}
+
+/// Every AC coefficient of every block, and the keyed order they are visited in
+#[derive(Debug, Clone)]
+struct Coefficients {
+    values: Vec<i16>,
+    order: Permutation,
+    /// Next step of `order` to look at
+    next: usize,
+}
+
+impl Coefficients {
+    fn read(jpeg: &Jpeg) -> Self {
+        let values = jpeg.components.iter()
+            .flat_map(|component| component.blocks.iter())
+            .flat_map(|block| block[1..].iter().copied())
+            .collect();
+        Coefficients { values, order: Permutation::Identity, next: 0 }
+    }
+
+    fn write(&self, jpeg: &mut Jpeg) {
+        let blocks = jpeg.components.iter_mut().flat_map(|component| component.blocks.iter_mut());
+        for (block, values) in blocks.zip(self.values.chunks(63)) {
+            block[1..].copy_from_slice(values);
+        }
+    }
+
+    /// Find the header of a stego JPEG, leaving `next` at the start of the payload.
+    /// Also returns the number of header bytes its parity repaired.
+    fn locate(data: &[u8], use_prng: bool, seed: Option<Seed>) -> Result<(Self, Header, usize), Error> {
+        let mut coefficients = Coefficients::read(&Jpeg::decode(data)?);
+        coefficients.order = match (use_prng, seed) {
+            (false, _) => Permutation::Identity,
+            (true, None) => return Err(Error::MissingSeed),
+            (true, Some(seed)) => Permutation::feistel(coefficients.values.len(), &seed),
+        };
+
+        let bytes = coefficients.extract(HEADER_LEN, 1).ok_or(Error::NoPayload)?;
+        let after_header = coefficients.next;
+        let parity = coefficients.extract(HEADER_PARITY, 1);
+        let (header, corrected) = Header::from_embedded_bytes(&bytes, parity.as_deref())?;
+        if header.ecc == Ecc::None {
+            coefficients.next = after_header;
+        }
+        Ok((coefficients, header, corrected))
+    }
+
+    /// Index of the next coefficient in order that can carry bits, i.e. is not zero
+    fn next_usable(&mut self) -> Option<usize> {
+        while self.next < self.values.len() {
+            let index = self.order.get(self.next);
+            self.next += 1;
+            if self.values[index] != 0 {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    /// The bits a group of coefficients carries: the XOR of the (1-based)
+    /// positions of those with an odd magnitude
+    fn hash(&self, group: &[usize]) -> u8 {
+        group.iter().enumerate()
+            .filter(|&(_, &index)| self.values[index].unsigned_abs() & 1 == 1)
+            .fold(0, |hash, (i, _)| hash ^ (i + 1) as u8)
+    }
+
+    /// Embed the header (with its parity) one bit per coefficient, then `payload`
+    /// `k` bits per group; `None` if the coefficients run out
+    fn embed(&mut self, header: &[u8], payload: &[u8], k: u8) -> Option<()> {
+        for i in 0..header.len() * 8 {
+            self.embed_group(bits(header, i, 1), 1)?;
+        }
+        for i in (0..payload.len() * 8).step_by(k as usize) {
+            self.embed_group(bits(payload, i, k), k)?;
+        }
+        Some(())
+    }
+
+    /// Make the next `2^k - 1` usable coefficients carry `value`, changing at most one,
+    /// and starting over with the next coefficient whenever a change zeroes one
+    fn embed_group(&mut self, value: u8, k: u8) -> Option<()> {
+        let n = (1 << k) - 1;
+        let mut group = Vec::with_capacity(n);
+        loop {
+            while group.len() < n {
+                group.push(self.next_usable()?);
+            }
+            let flip = (self.hash(&group) ^ value) as usize;
+            if flip == 0 {
+                return Some(());
+            }
+            let coefficient = &mut self.values[group[flip - 1]];
+            *coefficient -= coefficient.signum();
+            if *coefficient != 0 {
+                return Some(());
+            }
+            group.remove(flip - 1);
+        }
+    }
+
+    /// Read `len` bytes embedded `k` bits per group
+    fn extract(&mut self, len: usize, k: u8) -> Option<Vec<u8>> {
+        let n = (1 << k) - 1;
+        let mut out = vec![0u8; len];
+        for i in (0..len * 8).step_by(k as usize) {
+            let group: Vec<usize> = (0..n).map(|_| self.next_usable()).collect::<Option<_>>()?;
+            let value = self.hash(&group);
+            // The last group may hold fewer than `k` bits, padded with zeros at the low end
+            for bit in 0..(k as usize).min(len * 8 - i) {
+                let j = i + bit;
+                out[j / 8] |= ((value >> (k as usize - 1 - bit)) & 1) << (7 - j % 8);
+            }
+        }
+        Some(out)
+    }
+
+    /// Bytes embeddable one bit per coefficient. A coefficient of magnitude 2 or more
+    /// takes any bit; a ±1 takes a one, but shrinks for a zero, which then needs the
+    /// next coefficient too. With as many zeros as ones that is `1/2 + 1/2 · nonzero/large`
+    /// coefficients per bit, on average.
+    fn capacity(&self) -> usize {
+        let nonzero = self.values.iter().filter(|&&c| c != 0).count();
+        let large = self.values.iter().filter(|&&c| c.abs() > 1).count();
+        if large == 0 {
+            return 0;
+        }
+        2 * nonzero * large / (nonzero + large) / 8
+    }
+}
+
+/// `k` bits of `bytes` from bit `i` on, MSB first, padded with zeros past the end
+fn bits(bytes: &[u8], i: usize, k: u8) -> u8 {
+    (i..i + k as usize).fold(0, |acc, j| {
+        let bit = bytes.get(j / 8).map_or(0, |byte| (byte >> (7 - j % 8)) & 1);
+        (acc << 1) | bit
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Algorithm;
+    use crate::stegano::test_util::{self, temp_path};
+    use image::codecs::jpeg::JpegEncoder;
+
+    fn cover(width: u32, height: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        JpegEncoder::new_with_quality(&mut out, 90).encode_image(&test_util::cover(width, height)).unwrap();
+        out
+    }
+
+    #[test]
+    fn jpeg_roundtrip() {
+        let cover = cover(160, 120);
+        let message = Message::new(b"Hidden in the frequency domain");
+        for (algorithm, use_prng, ecc) in [
+            (Algorithm::None, false, Ecc::None),
+            (Algorithm::Aes, true, Ecc::None),
+            (Algorithm::ChaCha20Poly1305, true, Ecc::Low),
+        ] {
+            let options = EmbedOptions {
+                algorithm,
+                use_prng,
+                seed: use_prng.then(|| Seed::new("jpeg")),
+                ecc,
+                ..EmbedOptions::default()
+            };
+            let stego = embed_jpeg(&cover, &message, "key", &options).unwrap();
+            assert!(is_jpeg(&stego));
+            // Still a JPEG anyone can open
+            image::load_from_memory(&stego).unwrap();
+
+            let (header, extracted, corrected) = extract_jpeg(&stego, "key", use_prng, options.seed).unwrap();
+            assert_eq!((header.algorithm, header.ecc, corrected), (algorithm, ecc, 0));
+            assert_eq!(extracted, message);
+            // Short messages get the densest matrix encoding
+            assert_eq!(header.depth(), MAX_DEPTH);
+        }
+    }
+
+    #[test]
+    fn jpeg_only_touches_ac_coefficients() {
+        let cover = cover(64, 64);
+        let stego = embed_jpeg(&cover, &Message::new(b"dc stays put"), "", &EmbedOptions::default()).unwrap();
+        let (before, after) = (Jpeg::decode(&cover).unwrap(), Jpeg::decode(&stego).unwrap());
+        let blocks = |jpeg: &Jpeg| jpeg.components.iter().flat_map(|c| c.blocks.clone()).collect::<Vec<_>>();
+
+        let mut changed = 0;
+        for (old, new) in blocks(&before).iter().zip(&blocks(&after)) {
+            assert_eq!(old[0], new[0]);
+            for (&o, &n) in old[1..].iter().zip(&new[1..]) {
+                // Zeros stay zero, anything else only moves towards zero
+                assert!(o == n || (o.abs() == n.abs() + 1 && o.signum() * n.signum() >= 0), "{o} -> {n}");
+                changed += usize::from(o != n);
+            }
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn jpeg_falls_back_to_sparser_encodings() {
+        let cover = cover(64, 64);
+        let capacity = jpeg_capacity(&cover).unwrap();
+
+        // Too much for (1, 15, 4) matrix encoding, but fine one bit per coefficient
+        let message = Message::new(vec![0x5A; capacity / 2]);
+        let stego = embed_jpeg(&cover, &message, "", &EmbedOptions::default()).unwrap();
+        let (header, extracted, _) = extract_jpeg(&stego, "", false, None).unwrap();
+        assert!(header.depth() < MAX_DEPTH);
+        assert_eq!(extracted, message);
+
+        let message = Message::new(vec![0x5A; capacity * 2]);
+        let result = embed_jpeg(&cover, &message, "", &EmbedOptions::default());
+        assert!(matches!(result, Err(Error::CapacityExceeded { .. })));
+    }
+
+    #[test]
+    fn jpeg_file_is_verified() {
+        let path = temp_path("stego.jpg");
+        let message = Message::new(b"written and read back");
+        embed_jpeg_file(&cover(64, 64), &message, "", &EmbedOptions::default(), &path).unwrap();
+        let stego = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(extract_jpeg(&stego, "", false, None).unwrap().1, message);
+
+        let result = embed_jpeg_file(&cover(64, 64), &message, "", &EmbedOptions::default(), "stego.png");
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn jpeg_needs_the_seed() {
+        let cover = cover(96, 96);
+        let options = EmbedOptions { use_prng: true, seed: Some(Seed::new("right")), ..EmbedOptions::default() };
+        let stego = embed_jpeg(&cover, &Message::new(b"keyed order"), "", &options).unwrap();
+
+        assert!(read_jpeg_header(&stego, true, Some(Seed::new("right"))).unwrap().uses_prng());
+        assert!(matches!(read_jpeg_header(&stego, true, None), Err(Error::MissingSeed)));
+        assert!(read_jpeg_header(&stego, true, Some(Seed::new("wrong"))).is_err());
+        // An untouched JPEG has no container
+        assert!(matches!(read_jpeg_header(&cover, false, None), Err(Error::NoPayload)));
+    }
+}
@@ -0,0 +1,131 @@
+use image::{DynamicImage, GenericImageView};
+
+use crate::Error;
+use crate::crypto::Algorithm;
+use super::channels::Channels;
+use super::ecc::Ecc;
+use super::header::{Header, HEADER_LEN};
+
+/// Channels taken by the header, which is always embedded at one bit per channel
+pub const HEADER_SLOTS: usize = HEADER_LEN * 8;
+
+/// Where a container goes in a cover: how many channels it offers and how densely
+/// the payload is packed into them. Embedding, extraction and analysis all size
+/// payloads with this, so "fits" means exactly the same thing to each of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub pixels: usize,
+    /// Channels of each pixel that carry bits; never [`Channels::Auto`]
+    pub channels: Channels,
+    /// Payload bits per channel (1 to [`super::header::MAX_DEPTH`])
+    pub depth: u8,
+    /// Error correction, which adds parity after the header
+    pub ecc: Ecc,
+}
+
+impl Layout {
+    /// Layout for embedding into `img`, with `Auto` channels resolved against it
+    pub fn new(img: &DynamicImage, channels: Channels, depth: u8, ecc: Ecc) -> Self {
+        let (width, height) = img.dimensions();
+        Layout {
+            pixels: width as usize * height as usize,
+            channels: channels.resolve(img),
+            depth,
+            ecc,
+        }
+    }
+
+    /// Layout recorded in `header`, for a cover of `pixels` pixels
+    pub fn from_header(pixels: usize, header: &Header) -> Self {
+        Layout { pixels, channels: header.channels(), depth: header.depth(), ecc: header.ecc }
+    }
+
+    /// Channels available for the header and payload
+    pub fn slots(&self) -> usize {
+        self.pixels * self.channels.count()
+    }
+
+    /// Channels taken by the header and its parity, at one bit per channel
+    pub fn header_slots(&self) -> usize {
+        self.ecc.header_len() * 8
+    }
+
+    /// Channels a payload of `payload_len` bytes takes after the header;
+    /// the last one may be only partly used
+    pub fn payload_slots(&self, payload_len: usize) -> usize {
+        (payload_len * 8).div_ceil(self.depth as usize)
+    }
+
+    /// Header plus payload bytes that fit, parity included
+    pub fn capacity(&self) -> usize {
+        let slots = self.slots();
+        self.ecc.header_len().min(slots / 8) + slots.saturating_sub(self.header_slots()) * self.depth as usize / 8
+    }
+
+    /// Whether the header and a payload of `payload_len` bytes (after error correction) fit
+    pub fn fits(&self, payload_len: usize) -> bool {
+        self.header_slots() + self.payload_slots(payload_len) <= self.slots()
+    }
+
+    /// [`Error::CapacityExceeded`] unless a payload of `payload_len` bytes fits
+    pub fn check(&self, payload_len: usize) -> Result<(), Error> {
+        if self.fits(payload_len) {
+            Ok(())
+        } else {
+            Err(Error::CapacityExceeded {
+                needed: self.ecc.header_len() + payload_len,
+                available: self.capacity(),
+            })
+        }
+    }
+}
+
+/// Bytes embedded for an encoded message of `message_len` bytes: header plus
+/// ciphertext, and their parity with error correction
+pub fn container_len(message_len: usize, algorithm: Algorithm, ecc: Ecc) -> usize {
+    ecc.header_len() + ecc.encoded_len(algorithm.ciphertext_len(message_len))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_capacity_matches_fits() {
+        for channels in Channels::CONCRETE {
+            for depth in 1..=4 {
+                for pixels in [0, 10, 56, 57, 100, 333] {
+                    for ecc in [Ecc::None, Ecc::High] {
+                        let layout = Layout { pixels, channels, depth, ecc };
+                        let capacity = layout.capacity();
+                        let header_len = ecc.header_len();
+                        if capacity < header_len {
+                            assert!(!layout.fits(0), "{layout:?}");
+                            continue;
+                        }
+                        // The largest payload that fits is exactly the advertised capacity
+                        assert!(layout.fits(capacity - header_len), "{layout:?}");
+                        assert!(!layout.fits(capacity - header_len + 1), "{layout:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn layout_check_reports_capacity() {
+        let layout = Layout { pixels: 100, channels: Channels::Rgb, depth: 2, ecc: Ecc::None };
+        assert_eq!(layout.capacity(), 54);
+        assert!(layout.check(33).is_ok());
+        assert_eq!(
+            layout.check(34),
+            Err(Error::CapacityExceeded { needed: 55, available: 54 })
+        );
+
+        // Header parity takes 128 more channels, which held 32 payload bytes at depth 2
+        let layout = Layout { ecc: Ecc::Low, ..layout };
+        assert_eq!(layout.capacity(), 54 - 64 / 2 + 16);
+        assert_eq!(container_len(10, Algorithm::None, Ecc::Low), HEADER_LEN + 16 + 10 + 8);
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use crate::Error;
+
+/// Plaintext that gets encrypted and embedded: arbitrary bytes plus,
+/// for files, the original name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub data: Vec<u8>,
+    /// Original file name when the message was read from a file
+    pub file_name: Option<String>,
+}
+
+impl Message {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Message { data: data.into(), file_name: None }
+    }
+
+    /// Read a file to embed, remembering its name (without directories)
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let data = std::fs::read(path).map_err(|e| Error::Io(format!("Failed to read {path}: {e}")))?;
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::InvalidArgument(format!("Not a file path: {path}")))?;
+        Ok(Message { data, file_name: Some(file_name) })
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_name.is_some()
+    }
+
+    /// Serialize for encryption. Files carry metadata in front of the data:
+    /// `name_len: u16 | name | size: u64 | data`; plain messages are just the data.
+    pub fn encode(&self) -> Vec<u8> {
+        let Some(name) = &self.file_name else {
+            return self.data.clone();
+        };
+        let name = &name.as_bytes()[..name.len().min(u16::MAX as usize)];
+
+        let mut out = Vec::with_capacity(2 + name.len() + 8 + self.data.len());
+        out.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        out.extend_from_slice(name);
+        out.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    /// Inverse of [`Message::encode`]; `is_file` comes from the container header
+    pub fn decode(bytes: Vec<u8>, is_file: bool) -> Result<Self, Error> {
+        if !is_file {
+            return Ok(Message::new(bytes));
+        }
+
+        let corrupt = || Error::CorruptPayload("truncated file metadata".into());
+        let name_len = u16::from_be_bytes(bytes.get(0..2).ok_or_else(corrupt)?.try_into().unwrap()) as usize;
+        let name = bytes.get(2..2 + name_len).ok_or_else(corrupt)?;
+        let size_bytes = bytes.get(2 + name_len..10 + name_len).ok_or_else(corrupt)?;
+        let size = u64::from_be_bytes(size_bytes.try_into().unwrap());
+
+        let data = &bytes[10 + name_len..];
+        if data.len() as u64 != size {
+            return Err(Error::CorruptPayload(format!(
+                "file size mismatch: metadata says {size} bytes, found {}",
+                data.len()
+            )));
+        }
+
+        Ok(Message {
+            data: data.to_vec(),
+            file_name: Some(String::from_utf8_lossy(name).into_owned()),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message_plain_has_no_overhead() {
+        let msg = Message::new(b"Hello, World!".to_vec());
+        assert_eq!(msg.encode(), b"Hello, World!");
+        assert_eq!(Message::decode(msg.encode(), false).unwrap(), msg);
+    }
+
+    #[test]
+    fn message_file_roundtrip() {
+        let msg = Message {
+            data: vec![0x00, 0xFF, 0x80, 0x7F],
+            file_name: Some("key.bin".into()),
+        };
+        let decoded = Message::decode(msg.encode(), true).unwrap();
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn message_file_truncated() {
+        let msg = Message {
+            data: vec![1, 2, 3],
+            file_name: Some("a.zip".into()),
+        };
+        let mut bytes = msg.encode();
+        bytes.pop();
+        assert_eq!(
+            Message::decode(bytes, true).unwrap_err(),
+            Error::CorruptPayload("file size mismatch: metadata says 3 bytes, found 2".into())
+        );
+        assert_eq!(
+            Message::decode(vec![0], true).unwrap_err(),
+            Error::CorruptPayload("truncated file metadata".into())
+        );
+    }
+}
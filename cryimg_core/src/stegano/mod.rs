@@ -1,3 +1,15 @@
 pub mod embed;
 pub mod extract;
-pub mod analyze;
\ No newline at end of file
+pub mod analyze;
+pub mod detect;
+pub mod header;
+pub mod channels;
+pub mod compression;
+pub mod ecc;
+pub mod jpeg;
+pub mod layout;
+pub mod permutation;
+pub mod message;
+pub mod output;
+pub mod verify;#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
@@ -0,0 +1,111 @@
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use image::{DynamicImage, ImageFormat};
+
+use crate::Error;
+
+/// Formats that store pixels exactly, so embedded bits survive encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LosslessFormat {
+    #[default]
+    Png,
+    Bmp,
+    Tiff,
+    /// `image` only ships the lossless WebP encoder
+    WebP,
+}
+
+impl From<LosslessFormat> for ImageFormat {
+    fn from(format: LosslessFormat) -> Self {
+        match format {
+            LosslessFormat::Png => ImageFormat::Png,
+            LosslessFormat::Bmp => ImageFormat::Bmp,
+            LosslessFormat::Tiff => ImageFormat::Tiff,
+            LosslessFormat::WebP => ImageFormat::WebP,
+        }
+    }
+}
+
+/// What the extension of an output path asks for, and so which embedding can survive it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Pixels are stored exactly, so payloads hidden in them survive
+    Lossless(LosslessFormat),
+    /// Lossy; only a payload in the DCT coefficients survives, see [`super::jpeg`]
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// Format for `path`, from its extension. Formats that can carry neither kind
+    /// of payload are rejected.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path).map_err(|_| {
+            Error::InvalidArgument(format!("Cannot tell the image format of {} from its extension", path.display()))
+        })?;
+        match format {
+            ImageFormat::Png => Ok(OutputFormat::Lossless(LosslessFormat::Png)),
+            ImageFormat::Bmp => Ok(OutputFormat::Lossless(LosslessFormat::Bmp)),
+            ImageFormat::Tiff => Ok(OutputFormat::Lossless(LosslessFormat::Tiff)),
+            ImageFormat::WebP => Ok(OutputFormat::Lossless(LosslessFormat::WebP)),
+            ImageFormat::Jpeg => Ok(OutputFormat::Jpeg),
+            other => Err(Error::UnsupportedFormat(format!(
+                "{other:?} output cannot carry a payload; use PNG, BMP, TIFF or WebP, or JPEG with a JPEG cover"
+            ))),
+        }
+    }
+
+    /// The lossless format pixel-domain embedding needs
+    pub fn lossless(self) -> Result<LosslessFormat, Error> {
+        match self {
+            OutputFormat::Lossless(format) => Ok(format),
+            OutputFormat::Jpeg => Err(Error::UnsupportedFormat(
+                "JPEG output is lossy and would destroy a payload hidden in pixels; save as PNG, BMP, TIFF or WebP, \
+                 or embed into the DCT coefficients of a JPEG cover".into(),
+            )),
+        }
+    }
+}
+
+/// Encode a stego image into any seekable sink, e.g. a `Cursor<Vec<u8>>`
+pub fn write_image<W: Write + Seek>(
+    img: &DynamicImage,
+    sink: &mut W,
+    format: LosslessFormat,
+) -> Result<(), Error> {
+    img.write_to(sink, format.into())
+        .map_err(|e| Error::Io(format!("Failed to encode image: {e}")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use image::RgbaImage;
+
+    #[test]
+    fn output_lossless_formats_preserve_pixels() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |x, y| {
+            image::Rgba([x as u8, y as u8, (x * y) as u8 | 1, 255])
+        }));
+
+        for format in [LosslessFormat::Png, LosslessFormat::Bmp, LosslessFormat::Tiff, LosslessFormat::WebP] {
+            let mut buf = Cursor::new(Vec::new());
+            write_image(&img, &mut buf, format).unwrap();
+            let decoded = image::load_from_memory(buf.get_ref()).unwrap();
+            assert_eq!(decoded.to_rgba8(), img.to_rgba8(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn output_format_from_path() {
+        assert_eq!(OutputFormat::from_path("out.PNG").unwrap(), OutputFormat::Lossless(LosslessFormat::Png));
+        assert_eq!(OutputFormat::from_path("dir/out.tif").unwrap().lossless(), Ok(LosslessFormat::Tiff));
+        assert_eq!(OutputFormat::from_path("out.jpeg").unwrap(), OutputFormat::Jpeg);
+        assert!(matches!(OutputFormat::Jpeg.lossless(), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(OutputFormat::from_path("out.gif"), Err(Error::UnsupportedFormat(_))));
+        assert!(matches!(OutputFormat::from_path("out"), Err(Error::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,141 @@
+use std::hash::Hasher;
+
+use rand::{seq::SliceRandom, Rng};
+use siphasher::sip::SipHasher24;
+
+use crate::Error;
+use crate::utils::Seed;
+use super::header::Header;
+
+/// Feistel rounds; four rounds of a PRF already give a strong pseudo-random
+/// permutation (Luby–Rackoff), six leave some margin
+const ROUNDS: usize = 6;
+
+/// Order in which pixels are visited, as a map from step to x-major pixel index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Permutation {
+    /// Pixels in x-major order
+    Identity,
+    /// Keyed permutation computed one index at a time, in O(1) memory
+    Feistel(Feistel),
+    /// Fisher–Yates shuffle of every index; the order images embedded
+    /// with `--prng` used before [`Feistel`] existed
+    Shuffle(Vec<usize>),
+}
+
+impl Permutation {
+    pub fn feistel(len: usize, seed: &Seed) -> Self {
+        Permutation::Feistel(Feistel::new(len, seed))
+    }
+
+    /// Materializes and shuffles all `len` indices: 8 bytes per pixel
+    pub fn shuffle(len: usize, seed: &Seed) -> Self {
+        let mut order: Vec<usize> = (0..len).collect();
+        order.shuffle(&mut seed.rng());
+        Permutation::Shuffle(order)
+    }
+
+    /// The order `header` says its image was embedded in, over `len` pixels
+    pub fn for_header(header: &Header, len: usize, seed: Option<Seed>) -> Result<Self, Error> {
+        match seed {
+            _ if !header.uses_prng() => Ok(Permutation::Identity),
+            None => Err(Error::MissingSeed),
+            Some(seed) if header.uses_feistel() => Ok(Permutation::feistel(len, &seed)),
+            Some(seed) => Ok(Permutation::shuffle(len, &seed)),
+        }
+    }
+
+    /// Pixel index visited at step `i`
+    pub fn get(&self, i: usize) -> usize {
+        match self {
+            Permutation::Identity => i,
+            Permutation::Feistel(feistel) => feistel.get(i),
+            Permutation::Shuffle(order) => order[i],
+        }
+    }
+}
+
+/// Format-preserving permutation of `0..len`: a balanced Feistel network over the
+/// smallest even number of bits covering `len`, with cycle walking to stay in range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feistel {
+    len: usize,
+    half_bits: u32,
+    keys: [(u64, u64); ROUNDS],
+}
+
+impl Feistel {
+    pub fn new(len: usize, seed: &Seed) -> Self {
+        let bits = usize::BITS - len.saturating_sub(1).leading_zeros();
+        let mut rng = seed.rng();
+        Feistel {
+            len,
+            half_bits: bits.div_ceil(2).max(1),
+            keys: std::array::from_fn(|_| (rng.random(), rng.random())),
+        }
+    }
+
+    /// Image of `i`, which must be below `len`
+    pub fn get(&self, i: usize) -> usize {
+        debug_assert!(i < self.len);
+        // The network permutes a domain less than four times `len`, so on
+        // average fewer than four rounds of walking land back in range
+        let mut x = i as u64;
+        loop {
+            x = self.encrypt(x);
+            if x < self.len as u64 {
+                return x as usize;
+            }
+        }
+    }
+
+    fn encrypt(&self, x: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let (mut left, mut right) = (x >> self.half_bits, x & mask);
+        for &(k0, k1) in &self.keys {
+            let mut prf = SipHasher24::new_with_keys(k0, k1);
+            prf.write_u64(right);
+            (left, right) = (right, left ^ (prf.finish() & mask));
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feistel_is_a_permutation() {
+        let seed = Seed::new("feistel");
+        for len in [1, 2, 3, 4, 5, 17, 64, 1000, 4097] {
+            let feistel = Feistel::new(len, &seed);
+            let mut seen = vec![false; len];
+            for i in 0..len {
+                let j = feistel.get(i);
+                assert!(!seen[j], "{len}: {j} visited twice");
+                seen[j] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn feistel_depends_on_seed() {
+        let order = |seed: &str| {
+            let feistel = Feistel::new(10_000, &Seed::new(seed));
+            (0..32).map(|i| feistel.get(i)).collect::<Vec<_>>()
+        };
+        assert_eq!(order("a"), order("a"));
+        assert_ne!(order("a"), order("b"));
+        // Not just an offset of the identity
+        assert_ne!(order("a"), (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn feistel_handles_huge_domains() {
+        // A gigapixel image costs no more memory than a tiny one
+        let feistel = Feistel::new(1 << 30, &Seed::new("big"));
+        assert!((0..1000).all(|i| feistel.get(i) < 1 << 30));
+    }
+}
@@ -0,0 +1,18 @@
+//! Covers and scratch files shared by the tests and benchmarks
+
+use image::{DynamicImage, RgbImage};
+
+/// A path in the system temp directory, unique to this process
+pub fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("cryimg_{}_{name}", std::process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Opaque RGB cover with busy low bits in every channel
+pub fn cover(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 7 + y) as u8, (x ^ y) as u8, (x * y) as u8])
+    }))
+}
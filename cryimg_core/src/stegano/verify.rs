@@ -0,0 +1,255 @@
+use image::{DynamicImage, GenericImageView};
+
+use crate::Error;
+use crate::utils::Seed;
+use super::extract::extract_payload;
+use super::header::Header;
+use super::jpeg::extract_jpeg_payload;
+
+/// SSIM stabilizers for 8-bit values, `(0.01 · 255)²` and `(0.03 · 255)²`
+const SSIM_C1: f64 = 6.5025;
+const SSIM_C2: f64 = 58.5225;
+
+/// Side and step of the SSIM windows
+const SSIM_WINDOW: usize = 8;
+const SSIM_STEP: usize = 4;
+
+/// How far a stego image is from its cover
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    /// Mean squared error over the cover's channels (alpha only if it has one)
+    pub mse: f64,
+    /// Peak signal-to-noise ratio for `mse`, in dB; infinite when nothing changed
+    pub psnr: f64,
+    /// Mean structural similarity of the luma planes over 8x8 windows; 1.0 when identical
+    pub ssim: f64,
+    pub pixels: usize,
+    pub changed_pixels: usize,
+    /// Channel values that differ, over all pixels
+    pub changed_channels: usize,
+}
+
+/// Outcome of an embedding that was written out and read back, see [`verify_saved`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verification {
+    /// Header read back from the written image
+    pub header: Header,
+    /// How much embedding changed the cover, measured on the written image
+    pub metrics: Metrics,
+}
+
+/// Distortion metrics between a cover and a stego image of the same size
+pub fn compare_images(cover: &DynamicImage, stego: &DynamicImage) -> Result<Metrics, Error> {
+    if cover.dimensions() != stego.dimensions() {
+        return Err(Error::InvalidArgument(format!(
+            "Cannot compare a {:?} cover with a {:?} stego image", cover.dimensions(), stego.dimensions()
+        )));
+    }
+    let channels = if cover.color().has_alpha() { 4 } else { 3 };
+    let (before, after) = (cover.to_rgba8(), stego.to_rgba8());
+
+    let (mut squared_error, mut changed_pixels, mut changed_channels) = (0u64, 0, 0);
+    for (a, b) in before.pixels().zip(after.pixels()) {
+        let changed = (0..channels).filter(|&c| a[c] != b[c]).count();
+        changed_pixels += usize::from(changed > 0);
+        changed_channels += changed;
+        squared_error += (0..channels).map(|c| (a[c] as i64 - b[c] as i64).pow(2) as u64).sum::<u64>();
+    }
+
+    let pixels = before.len() / 4;
+    let mse = if pixels == 0 { 0.0 } else { squared_error as f64 / (pixels * channels) as f64 };
+    Ok(Metrics {
+        mse,
+        psnr: 10.0 * (255.0f64 * 255.0 / mse).log10(),
+        ssim: ssim(&luma(cover), &luma(stego), cover.width() as usize),
+        pixels,
+        changed_pixels,
+        changed_channels,
+    })
+}
+
+/// BT.601 luma of every pixel, row by row
+fn luma(img: &DynamicImage) -> Vec<f64> {
+    img.to_rgb8().pixels().map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64).collect()
+}
+
+/// Mean SSIM (Wang et al., 2004) over windows of [`SSIM_WINDOW`] pixels a side,
+/// [`SSIM_STEP`] apart; an image smaller than a window is one window
+fn ssim(a: &[f64], b: &[f64], width: usize) -> f64 {
+    let height = a.len().checked_div(width).unwrap_or(0);
+    if height == 0 {
+        return 1.0;
+    }
+    let (window_w, window_h) = (SSIM_WINDOW.min(width), SSIM_WINDOW.min(height));
+    let starts = |len: usize, window: usize| (0..=len - window).step_by(SSIM_STEP);
+
+    let (mut total, mut windows) = (0.0, 0);
+    for y in starts(height, window_h) {
+        for x in starts(width, window_w) {
+            let values = (y..y + window_h).flat_map(|row| (x..x + window_w).map(move |col| row * width + col));
+            let n = (window_w * window_h) as f64;
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for i in values {
+                sa += a[i];
+                sb += b[i];
+                saa += a[i] * a[i];
+                sbb += b[i] * b[i];
+                sab += a[i] * b[i];
+            }
+            let (mean_a, mean_b) = (sa / n, sb / n);
+            let (var_a, var_b) = (saa / n - mean_a * mean_a, sbb / n - mean_b * mean_b);
+            let covariance = sab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+/// Re-open the image written to `path` and check that it gives back `header` and
+/// `payload` (as embedded, error correction included) exactly, so a write that lost
+/// the message is reported as a failure rather than found out at extraction time.
+/// Returns the header read back and the reloaded image; on failure the file is removed.
+pub fn verify_saved(
+    path: &str,
+    header: &Header,
+    payload: &[u8],
+    seed: Option<Seed>,
+) -> Result<(Header, DynamicImage), Error> {
+    let result = image::open(path)
+        .map_err(|e| Error::Io(format!("Failed to re-open {path} for verification: {e}")))
+        .and_then(|img| {
+            let found = extract_payload(&img, header.uses_prng(), seed);
+            check(path, header, payload, found).map(|header| (header, img))
+        });
+    discard_on_failure(path, result)
+}
+
+/// [`verify_saved`] for a JPEG written by [`super::jpeg::embed_jpeg_file`]
+pub fn verify_saved_jpeg(
+    path: &str,
+    header: &Header,
+    payload: &[u8],
+    seed: Option<Seed>,
+) -> Result<(Header, DynamicImage), Error> {
+    let result = std::fs::read(path)
+        .map_err(|e| Error::Io(format!("Failed to re-open {path} for verification: {e}")))
+        .and_then(|data| {
+            let found = extract_jpeg_payload(&data, header.uses_prng(), seed);
+            let header = check(path, header, payload, found)?;
+            let img = image::load_from_memory(&data)
+                .map_err(|e| Error::Io(format!("Failed to decode {path} for verification: {e}")))?;
+            Ok((header, img))
+        });
+    discard_on_failure(path, result)
+}
+
+/// Remove a written image that failed verification, so it cannot be mistaken for a good one
+fn discard_on_failure<T>(path: &str, result: Result<T, Error>) -> Result<T, Error> {
+    if result.is_err() {
+        std::fs::remove_file(path).ok();
+    }
+    result
+}
+
+fn check(
+    path: &str,
+    header: &Header,
+    payload: &[u8],
+    found: Result<(Header, Vec<u8>, usize), Error>,
+) -> Result<Header, Error> {
+    let lost = |reason: String| Error::CorruptPayload(format!("{path} does not give back the embedded payload: {reason}"));
+    let (found, data, corrected) = found.map_err(|e| lost(e.to_string()))?;
+    if found != *header {
+        return Err(lost("its header differs".into()));
+    }
+    // Repairs mean bits were lost even if the parity covered for them
+    if corrected > 0 || header.ecc.encode(&data) != payload {
+        return Err(lost("its payload differs".into()));
+    }
+    Ok(found)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Algorithm, kdf::Kdf};
+    use crate::stegano::embed::{embed_message, embed_payload, EmbedOptions, EmbedStrategy};
+    use crate::stegano::jpeg::embed_jpeg_file;
+    use crate::stegano::message::Message;
+    use crate::stegano::test_util::temp_path;
+    use image::{DynamicImage, RgbImage};
+
+    fn cover() -> DynamicImage {
+        crate::stegano::test_util::cover(32, 32)
+    }
+
+    #[test]
+    fn verify_saved_image() {
+        let path = temp_path("verify.png");
+        let payload = b"checked after saving";
+        let header = Header::new(payload, Algorithm::None, Kdf::None, "", false);
+        embed_payload(&cover(), &header, payload, None, EmbedStrategy::Replace, "", &path).unwrap();
+        let (found, img) = verify_saved(&path, &header, payload, None).unwrap();
+        assert_eq!(found, header);
+        assert_eq!(img.dimensions(), (32, 32));
+
+        // Something else at that path no longer carries the payload, and is not kept
+        cover().save(&path).unwrap();
+        let result = verify_saved(&path, &header, payload, None);
+        let kept = std::path::Path::new(&path).exists();
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(result, Err(Error::CorruptPayload(_))));
+        assert!(!kept);
+    }
+
+    #[test]
+    fn verify_metrics() {
+        let identical = compare_images(&cover(), &cover()).unwrap();
+        assert_eq!((identical.mse, identical.changed_pixels, identical.changed_channels), (0.0, 0, 0));
+        assert_eq!((identical.psnr, identical.ssim), (f64::INFINITY, 1.0));
+
+        // One channel of one pixel off by 3, out of 32 · 32 · 3 values
+        let mut stego = cover().to_rgb8();
+        stego.get_pixel_mut(5, 9)[1] += 3;
+        let metrics = compare_images(&cover(), &DynamicImage::ImageRgb8(stego)).unwrap();
+        assert_eq!(metrics.mse, 9.0 / 3072.0);
+        assert!((metrics.psnr - 10.0 * (65025.0f64 / metrics.mse).log10()).abs() < 1e-9);
+        assert!(metrics.ssim < 1.0 && metrics.ssim > 0.99);
+        assert_eq!((metrics.pixels, metrics.changed_pixels, metrics.changed_channels), (1024, 1, 1));
+
+        let small = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        assert!(matches!(compare_images(&cover(), &small), Err(Error::InvalidArgument(_))));
+        assert_eq!(compare_images(&small, &small).unwrap().ssim, 1.0);
+    }
+
+    #[test]
+    fn verify_embedding_png_and_jpeg() {
+        let path = temp_path("verify_embedding.png");
+        let options = EmbedOptions { algorithm: Algorithm::Xor, ..Default::default() };
+        let verification = embed_message(&cover(), b"verified end to end", "key", &options, &path);
+        std::fs::remove_file(&path).ok();
+        let verification = verification.unwrap();
+        assert_eq!(verification.header.algorithm, Algorithm::Xor);
+        assert!(verification.metrics.changed_channels > 0);
+        assert!(verification.metrics.psnr > 40.0);
+
+        let mut jpeg_cover = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg_cover, 90).encode_image(&cover()).unwrap();
+        let path = temp_path("verify_embedding.jpg");
+        let verification = embed_jpeg_file(&jpeg_cover, &Message::new(b"verified end to end"), "", &EmbedOptions::default(), &path);
+        std::fs::remove_file(&path).ok();
+        assert!(verification.unwrap().metrics.ssim > 0.9);
+    }
+
+    #[test]
+    fn verify_rejects_lossy_output() {
+        let path = temp_path("lossy.jpg");
+        let result = embed_message(&cover(), b"would be lost", "", &EmbedOptions::default(), &path);
+        assert!(matches!(result, Err(Error::UnsupportedFormat(_))));
+        // Rejected before anything is written
+        assert!(!std::path::Path::new(&path).exists());
+    }
+}
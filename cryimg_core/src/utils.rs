@@ -1,9 +1,61 @@
 use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use siphasher::sip::SipHasher13;
+
+use crate::Error;
+use crate::crypto::kdf::order_seed;
+
+/// Domain separation for [`Seed::new`], so the same string used as a key
+/// and as a seed never yields related values
+const SEED_CONTEXT: &[u8] = b"cryimg-prng-seed-v1";
+
+/// Seed for the PRNG that scrambles the embedding order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seed {
+    /// SHA-256 of the seed string, used as the full 256-bit ChaCha20 seed
+    Stable([u8; 32]),
+    /// 64-bit hash from [`string_to_seed`], expanded with `seed_from_u64`
+    /// (images embedded with `--seed` before stable seeds existed)
+    Legacy(u64),
+}
+
+impl Seed {
+    /// Derive a seed from any string; the result is fixed by this specification
+    /// and does not depend on the toolchain
+    pub fn new(seed_str: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(SEED_CONTEXT);
+        hasher.update(seed_str.as_bytes());
+        Seed::Stable(hasher.finalize().into())
+    }
+
+    /// Derive the seed from the encryption passphrase instead of a separate
+    /// secret, see [`crate::crypto::kdf::order_seed`]
+    pub fn from_key(key: &str) -> Result<Self, Error> {
+        Ok(Seed::Stable(order_seed(key)?))
+    }
+
+    /// The seed older versions derived from `seed_str`
+    pub fn legacy(seed_str: &str) -> Self {
+        Seed::Legacy(string_to_seed(seed_str))
+    }
+
+    pub fn rng(&self) -> ChaCha20Rng {
+        match *self {
+            Seed::Stable(seed) => ChaCha20Rng::from_seed(seed),
+            Seed::Legacy(seed) => ChaCha20Rng::seed_from_u64(seed),
+        }
+    }
+}
 
 /// Converts a seed string into a deterministic u64 value for PRNG.
+/// This is the legacy derivation: older versions used `DefaultHasher`, which is
+/// SipHash-1-3 with zero keys; it is pinned here since std does not guarantee it.
 pub fn string_to_seed(seed_str: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
+    let mut hasher = SipHasher13::new_with_keys(0, 0);
     seed_str.hash(&mut hasher);
     hasher.finish()
 }
@@ -12,6 +64,7 @@ pub fn string_to_seed(seed_str: &str) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::RngCore;
 
     #[test]
     fn test_string_to_seed() {
@@ -37,4 +90,27 @@ mod tests {
         assert_eq!(seed1, seed2); // Same special chars should yield same seed
         assert_ne!(seed1, seed3); // Different special chars should yield different seed
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_string_to_seed_matches_old_default_hasher() {
+        // Values produced by `DefaultHasher` in the releases that wrote legacy images
+        assert_eq!(string_to_seed("test_seed"), 0x60c5_de2f_3e84_0158);
+        assert_eq!(string_to_seed(""), 0x3040_6ea5_23c5_3def);
+    }
+
+    #[test]
+    fn test_stable_seed_is_specified() {
+        let Seed::Stable(bytes) = Seed::new("test_seed") else { panic!("expected a stable seed") };
+        let expected: [u8; 32] = Sha256::digest(b"cryimg-prng-seed-v1test_seed").into();
+        assert_eq!(bytes, expected);
+        assert_ne!(Seed::new("test_seed"), Seed::new("test_seed2"));
+    }
+
+    #[test]
+    fn test_seed_modes_give_different_orders() {
+        let mut stable = Seed::new("test_seed").rng();
+        let mut legacy = Seed::legacy("test_seed").rng();
+        assert_ne!(stable.next_u64(), legacy.next_u64());
+        assert_eq!(Seed::legacy("test_seed").rng().next_u64(), ChaCha20Rng::seed_from_u64(0x60c5_de2f_3e84_0158).next_u64());
+    }
+}